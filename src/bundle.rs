@@ -0,0 +1,741 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::config::PYPROJECT_FILE;
+use crate::diff::USER_PATCH_DIR;
+use crate::fixes::USER_CATALOG_FILE;
+use crate::torch::{self, UV_LOCK_FILE};
+use crate::{
+    App, OutputMode, WHEELHOUSE, cache, doctor, find_program, prepend_path_dir,
+    remove_dir_if_exists, run_command, sources,
+};
+
+pub const DEFAULT_BUNDLE_FILE: &str = "mmaction-bundle.tar.gz";
+/// Where `prefetch` assembles a bundle and `install --from-bundle` unpacks one.
+const STAGING_DIR: &str = ".mmaction-bundle";
+/// The single top-level directory inside the archive.
+const ROOT_DIR: &str = "mmaction-bundle";
+const INFO_FILE: &str = "bundle.json";
+const MANIFEST_FILE: &str = "manifest.toml";
+const FIXES_FILE: &str = "fixes.toml";
+const SOURCES_DIR: &str = "sources";
+/// The built package wheels and every wheel the install needs besides them.
+const WHEELS_DIR: &str = "wheels";
+const BIN_DIR: &str = "bin";
+/// uv-managed Python installations, as in `uv python dir`.
+const PYTHON_DIR: &str = "python";
+/// The project's locked dependencies, exported from uv.lock.
+const REQUIREMENTS_FILE: &str = "requirements.txt";
+const PIP_TOOLING: &[&str] = &["pip", "setuptools<81", "wheel"];
+
+/// `bundle.json`: what the archive was built from, for people and tools
+/// inspecting it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleInfo {
+    pub created_at: String,
+    pub packages: Vec<BundledPackage>,
+    pub uv: Option<String>,
+    /// The bundled Python installation, such as `cpython-3.12.8-linux-x86_64-gnu`.
+    #[serde(default)]
+    pub python: Option<String>,
+    /// The built package wheels, imported into the wheelhouse.
+    pub wheels: Vec<String>,
+    /// Torch, the pip tooling and the project's dependencies, installed
+    /// straight from the bundle.
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundledPackage {
+    pub name: String,
+    pub version: String,
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    pub repository: String,
+    pub commit: Option<String>,
+}
+
+/// An unpacked bundle.
+pub struct Bundle {
+    pub archive: PathBuf,
+    dir: PathBuf,
+    /// A dry run's scratch copy, removed with the bundle.
+    scratch: Option<PathBuf>,
+    pub info: BundleInfo,
+}
+
+impl Bundle {
+    /// Unpacks `archive` into `.mmaction-bundle`, replacing an earlier one.
+    /// A dry run still needs the bundle's manifest, so it unpacks into a
+    /// scratch directory under the system temp dir instead.
+    pub fn unpack(archive: &Path, dry_run: bool) -> Result<Self> {
+        if !archive.is_file() {
+            bail!("bundle {} does not exist", archive.display());
+        }
+        let staging = if dry_run {
+            std::env::temp_dir().join(format!("mmaction-bundle-{}", std::process::id()))
+        } else {
+            PathBuf::from(STAGING_DIR)
+        };
+        remove_dir_if_exists(&staging)?;
+        fs::create_dir_all(&staging)
+            .with_context(|| format!("failed to create {}", staging.display()))?;
+        let scratch = dry_run.then(|| staging.clone());
+
+        let output = Command::new("tar")
+            .arg("-xzf")
+            .arg(archive)
+            .arg("-C")
+            .arg(&staging)
+            .stdin(Stdio::null())
+            .output()
+            .context("failed to run tar")?;
+        if !output.status.success() {
+            bail!(
+                "failed to unpack bundle {}: {}",
+                archive.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let dir = staging.join(ROOT_DIR);
+        let info_path = dir.join(INFO_FILE);
+        let content = fs::read_to_string(&info_path).with_context(|| {
+            format!(
+                "{} is not an mmaction-install bundle (no {ROOT_DIR}/{INFO_FILE})",
+                archive.display()
+            )
+        })?;
+        let info = serde_json::from_str(&content)
+            .with_context(|| format!("invalid {}", info_path.display()))?;
+        Ok(Self {
+            archive: archive.to_path_buf(),
+            dir,
+            scratch,
+            info,
+        })
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dir.join(MANIFEST_FILE)
+    }
+
+    pub fn patch_dir(&self) -> PathBuf {
+        self.dir.join(USER_PATCH_DIR)
+    }
+
+    pub fn fixes_path(&self) -> PathBuf {
+        self.dir.join(FIXES_FILE)
+    }
+
+    /// Moves the bundle's sources into the source cache, its package wheels
+    /// into the wheelhouse and its Python among uv's, puts its uv first on
+    /// PATH and points uv and pip at its wheels.
+    pub fn import(&self, app: &App) -> Result<()> {
+        let sources_dir = self.dir.join(SOURCES_DIR);
+        for package in &app.packages {
+            if app.dry_run {
+                app.plan(&format!(
+                    "would import the {} {} source into {}",
+                    package.name,
+                    package.git_ref,
+                    sources::cached_dir(package)?.display()
+                ));
+            } else if !sources::import(package, &sources_dir)? {
+                bail!(
+                    "bundle {} has no source for {} {}",
+                    self.archive.display(),
+                    package.name,
+                    package.git_ref
+                );
+            }
+        }
+
+        if !self.info.wheels.is_empty() {
+            if app.dry_run {
+                app.plan(&format!("would copy the bundled wheels into {WHEELHOUSE}"));
+            } else {
+                self.import_wheels(Path::new(WHEELHOUSE))?;
+            }
+        }
+
+        let bin_dir = self.dir.join(BIN_DIR);
+        if bin_dir.join("uv").is_file() {
+            app.plan(&format!("using the bundled uv from {}", bin_dir.display()));
+            let absolute = fs::canonicalize(&bin_dir)
+                .with_context(|| format!("failed to resolve {}", bin_dir.display()))?;
+            prepend_path_dir(&absolute)?;
+        }
+
+        if let Some(python) = &self.info.python {
+            let python_dir = PathBuf::from(uv_stdout(&["python", "dir"])?);
+            if app.dry_run {
+                app.plan(&format!(
+                    "would import Python {python} into {}",
+                    python_dir.display()
+                ));
+            } else {
+                import_python(&self.dir.join(PYTHON_DIR), &python_dir)?;
+            }
+        }
+
+        let wheels_dir = self.dir.join(WHEELS_DIR);
+        if wheels_dir.is_dir() {
+            let absolute = fs::canonicalize(&wheels_dir)
+                .with_context(|| format!("failed to resolve {}", wheels_dir.display()))?;
+            app.plan(&format!(
+                "uv and pip find wheels in {} (--find-links)",
+                absolute.display()
+            ));
+            // Like UV_OFFLINE, covers the venv's pip tooling and torch as well
+            // as the build environment `uv sync` makes for the project.
+            unsafe {
+                std::env::set_var("UV_FIND_LINKS", &absolute);
+                std::env::set_var("PIP_FIND_LINKS", &absolute);
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs the project's locked dependencies from the bundle, so that
+    /// `uv sync` finds them installed instead of looking for them in uv's
+    /// cache.
+    pub fn install_dependencies(&self, app: &App) -> Result<()> {
+        let requirements = self.dir.join(REQUIREMENTS_FILE);
+        if !requirements.is_file() {
+            app.plan("the bundle has no locked dependencies");
+            return Ok(());
+        }
+        let mut install = Command::new("uv");
+        install
+            .arg("pip")
+            .arg("install")
+            .arg("--python")
+            .arg(app.python_bin())
+            .arg("--no-index")
+            .arg("--find-links")
+            .arg(self.dir.join(WHEELS_DIR))
+            .arg("-r")
+            .arg(&requirements);
+        run_command(
+            app,
+            "install the locked dependencies",
+            install,
+            OutputMode::Quiet,
+        )
+    }
+
+    /// Copies the built package wheels and their build records, but not the
+    /// dependency wheels, into `wheelhouse`.
+    fn import_wheels(&self, wheelhouse: &Path) -> Result<()> {
+        fs::create_dir_all(wheelhouse)
+            .with_context(|| format!("failed to create {}", wheelhouse.display()))?;
+        let wheels_dir = self.dir.join(WHEELS_DIR);
+        for wheel in &self.info.wheels {
+            let wheel = wheels_dir.join(wheel);
+            for file in [wheel.clone(), cache::record_path(&wheel)] {
+                if file.is_file() {
+                    fs::copy(&file, wheelhouse.join(cache::file_name(&file)))
+                        .with_context(|| format!("failed to copy {}", file.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Bundle {
+    fn drop(&mut self) {
+        if let Some(scratch) = &self.scratch {
+            let _ = fs::remove_dir_all(scratch);
+        }
+    }
+}
+
+/// Assembles the bundle for the selected packages, whose sources must be in
+/// the source cache, and archives it to `output`.
+pub fn write(app: &App, output: &Path, with_wheels: bool) -> Result<()> {
+    let staging = Path::new(STAGING_DIR).join(ROOT_DIR);
+    let uv = find_program("uv");
+    if app.dry_run {
+        app.plan(&format!(
+            "would bundle Python {}, torch, the pip tooling, the locked dependencies, the {} sources, patches and {}{}",
+            app.python_version,
+            app.packages
+                .iter()
+                .map(|package| format!("{} {}", package.name, package.git_ref))
+                .collect::<Vec<_>>()
+                .join(", "),
+            uv.as_ref()
+                .map_or("no uv binary".to_string(), |uv| uv.display().to_string()),
+            if with_wheels { " with the wheels" } else { "" }
+        ));
+    } else {
+        remove_dir_if_exists(STAGING_DIR)?;
+        fs::create_dir_all(&staging)
+            .with_context(|| format!("failed to create {}", staging.display()))?;
+        let info = stage(app, &staging, uv.as_deref(), with_wheels)?;
+        let json =
+            serde_json::to_string_pretty(&info).context("failed to serialize bundle info")?;
+        fs::write(staging.join(INFO_FILE), json + "\n")
+            .with_context(|| format!("failed writing {INFO_FILE}"))?;
+    }
+
+    let mut tar = Command::new("tar");
+    tar.arg("-czf")
+        .arg(output)
+        .arg("-C")
+        .arg(STAGING_DIR)
+        .arg(ROOT_DIR);
+    run_command(app, "archive bundle", tar, OutputMode::Quiet)?;
+    if !app.dry_run {
+        remove_dir_if_exists(STAGING_DIR)?;
+    }
+    Ok(())
+}
+
+fn stage(app: &App, staging: &Path, uv: Option<&Path>, with_wheels: bool) -> Result<BundleInfo> {
+    // The selection is already made; the bundle installs exactly these.
+    let mut manifest = app.manifest.clone();
+    for package in &mut manifest.packages {
+        package.optional = false;
+    }
+    fs::write(staging.join(MANIFEST_FILE), manifest.to_toml()?)
+        .with_context(|| format!("failed writing {MANIFEST_FILE}"))?;
+    if Path::new(USER_CATALOG_FILE).is_file() {
+        fs::copy(USER_CATALOG_FILE, staging.join(FIXES_FILE))
+            .with_context(|| format!("failed to copy {USER_CATALOG_FILE}"))?;
+    }
+
+    let mut packages = Vec::new();
+    let mut wheels = Vec::new();
+    for package in &app.packages {
+        let stamp = sources::cached(package)?.with_context(|| {
+            format!(
+                "no cached source for {} {}; fetch it first",
+                package.name, package.git_ref
+            )
+        })?;
+        sources::export(package, &staging.join(SOURCES_DIR))?;
+
+        let patches = Path::new(USER_PATCH_DIR).join(&package.name);
+        if patches.is_dir() {
+            sources::copy_tree(&patches, &staging.join(USER_PATCH_DIR).join(&package.name))?;
+        }
+
+        if with_wheels {
+            let wheels_dir = staging.join(WHEELS_DIR);
+            fs::create_dir_all(&wheels_dir)
+                .with_context(|| format!("failed to create {}", wheels_dir.display()))?;
+            for wheel in package.version_wheels()? {
+                for file in [wheel.clone(), cache::record_path(&wheel)] {
+                    if file.is_file() {
+                        fs::copy(&file, wheels_dir.join(cache::file_name(&file)))
+                            .with_context(|| format!("failed to copy {}", file.display()))?;
+                    }
+                }
+                wheels.push(cache::file_name(&wheel));
+            }
+        }
+
+        packages.push(BundledPackage {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            git_ref: package.git_ref.name().to_string(),
+            repository: package.source.origin(),
+            commit: stamp.commit,
+        });
+    }
+
+    if let Some(uv) = uv {
+        let bin_dir = staging.join(BIN_DIR);
+        fs::create_dir_all(&bin_dir)
+            .with_context(|| format!("failed to create {}", bin_dir.display()))?;
+        let target = bin_dir.join("uv");
+        fs::copy(uv, &target).with_context(|| format!("failed to copy {}", uv.display()))?;
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed to make {} executable", target.display()))?;
+    }
+
+    // Offline, the bundle can only carry what is cached; `prefetch` warns.
+    let (python, dependencies) = if app.offline {
+        (None, Vec::new())
+    } else {
+        let python = stage_python(app, &staging.join(PYTHON_DIR))?;
+        let dependencies = stage_dependencies(app, staging, &python)?;
+        (Some(python), dependencies)
+    };
+
+    Ok(BundleInfo {
+        created_at: cache::utc_timestamp(),
+        packages,
+        uv: uv.and_then(doctor::tool_version),
+        python,
+        wheels,
+        dependencies,
+    })
+}
+
+/// Installs the run's Python with uv, unless uv already manages one, and
+/// copies the installation into `python_dir`. Returns its directory name.
+fn stage_python(app: &App, python_dir: &Path) -> Result<String> {
+    let mut install = Command::new("uv");
+    install
+        .arg("python")
+        .arg("install")
+        .arg(&app.python_version);
+    run_command(
+        app,
+        &format!("install Python {}", app.python_version),
+        install,
+        OutputMode::Quiet,
+    )?;
+    let interpreter = uv_stdout(&["python", "find", "--managed-python", &app.python_version])?;
+    copy_python(
+        Path::new(&uv_stdout(&["python", "dir"])?),
+        Path::new(&interpreter),
+        python_dir,
+    )
+}
+
+/// Copies the uv-managed installation of `interpreter`, one of the
+/// directories of `uv_python_dir`, into `python_dir`.
+fn copy_python(uv_python_dir: &Path, interpreter: &Path, python_dir: &Path) -> Result<String> {
+    let resolve = |path: &Path| {
+        fs::canonicalize(path).with_context(|| format!("failed to resolve {}", path.display()))
+    };
+    let uv_python_dir = resolve(uv_python_dir)?;
+    let interpreter = resolve(interpreter)?;
+    let Some(name) = interpreter
+        .strip_prefix(&uv_python_dir)
+        .ok()
+        .and_then(|relative| relative.components().next())
+        .map(|name| name.as_os_str().to_string_lossy().into_owned())
+    else {
+        bail!(
+            "{} is not a uv-managed Python in {}",
+            interpreter.display(),
+            uv_python_dir.display()
+        );
+    };
+    sources::copy_tree(&uv_python_dir.join(&name), &python_dir.join(&name))?;
+    Ok(name)
+}
+
+/// Adds the bundled Python installations that uv does not have yet to
+/// `uv_python_dir`, where `uv venv --python` finds them offline.
+fn import_python(python_dir: &Path, uv_python_dir: &Path) -> Result<()> {
+    let entries = fs::read_dir(python_dir)
+        .with_context(|| format!("failed to list {}", python_dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to list {}", python_dir.display()))?;
+        let target = uv_python_dir.join(entry.file_name());
+        if !target.exists() {
+            sources::copy_tree(&entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Downloads the wheels of torch, the pip tooling, the project's build
+/// requirements and its locked dependencies into the bundle, with pip run
+/// by the bundled Python. Returns the downloaded wheel names.
+fn stage_dependencies(app: &App, staging: &Path, python: &str) -> Result<Vec<String>> {
+    let wheels_dir = staging.join(WHEELS_DIR);
+    fs::create_dir_all(&wheels_dir)
+        .with_context(|| format!("failed to create {}", wheels_dir.display()))?;
+
+    let requirements = if Path::new(UV_LOCK_FILE).is_file() {
+        let path = staging.join(REQUIREMENTS_FILE);
+        let mut export = Command::new("uv");
+        export
+            .arg("export")
+            .arg("--frozen")
+            .arg("--no-hashes")
+            .arg("--no-emit-project")
+            .arg("--format")
+            .arg("requirements-txt")
+            .arg("--output-file")
+            .arg(&path);
+        // Built from source by the installer, never downloaded.
+        for package in &app.packages {
+            export.arg("--no-emit-package").arg(&package.name);
+        }
+        run_command(
+            app,
+            "export the locked dependencies",
+            export,
+            OutputMode::Quiet,
+        )?;
+        Some(path)
+    } else {
+        None
+    };
+
+    let mut extra = build_requirements()?;
+    let torch = torch::resolve()?;
+    if let Some(torch) = &torch {
+        extra.push(torch.requirement.clone());
+    }
+    let bundled = wheel_names(&wheels_dir)?;
+    let download = download_command(
+        &staging.join(PYTHON_DIR).join(python).join("bin/python3"),
+        &wheels_dir,
+        &extra,
+        torch.as_ref().and_then(|torch| torch.index_url.as_deref()),
+        requirements.as_deref(),
+    );
+    run_command(
+        app,
+        "download dependency wheels",
+        download,
+        OutputMode::Quiet,
+    )?;
+
+    Ok(wheel_names(&wheels_dir)?
+        .into_iter()
+        .filter(|wheel| !bundled.contains(wheel))
+        .collect())
+}
+
+/// `pip download` of the pip tooling and `requirements`, plus the packages
+/// `requirements_file` lists, as wheels only: the target has no compiler
+/// toolchain guarantees and no index to fall back on.
+fn download_command(
+    python: &Path,
+    wheels_dir: &Path,
+    requirements: &[String],
+    extra_index_url: Option<&str>,
+    requirements_file: Option<&Path>,
+) -> Command {
+    let mut download = Command::new("uv");
+    download
+        .arg("run")
+        .arg("--no-project")
+        .arg("--python")
+        .arg(python)
+        .arg("--with")
+        .arg("pip")
+        .arg("--")
+        .arg("python")
+        .arg("-m")
+        .arg("pip")
+        .arg("download")
+        .arg("--only-binary")
+        .arg(":all:")
+        .arg("--dest")
+        .arg(wheels_dir);
+    if let Some(index_url) = extra_index_url {
+        download.arg("--extra-index-url").arg(index_url);
+    }
+    download.args(PIP_TOOLING).args(requirements);
+    if let Some(file) = requirements_file {
+        download.arg("-r").arg(file);
+    }
+    download
+}
+
+/// `[build-system] requires` from pyproject.toml, which `uv sync` needs to
+/// build the project itself.
+fn build_requirements() -> Result<Vec<String>> {
+    let path = Path::new(PYPROJECT_FILE);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed reading {PYPROJECT_FILE}"))?;
+    let pyproject: toml::Table =
+        toml::from_str(&content).with_context(|| format!("invalid {PYPROJECT_FILE}"))?;
+    Ok(pyproject
+        .get("build-system")
+        .and_then(|build| build.get("requires"))
+        .and_then(|requires| requires.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|requirement| requirement.as_str().map(ToString::to_string))
+        .collect())
+}
+
+fn wheel_names(dir: &Path) -> Result<Vec<String>> {
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to list {}", dir.display()))?;
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to list {}", dir.display()))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "whl") {
+            names.push(cache::file_name(&path));
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// The trimmed stdout of a uv command that only reports something.
+fn uv_stdout(args: &[&str]) -> Result<String> {
+    let output = Command::new("uv")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("failed to run uv {}", args.join(" ")))?;
+    if !output.status.success() {
+        bail!(
+            "uv {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "mmaction-install-bundle-{name}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn stages_python_and_wheels_in_the_bundle_layout() {
+        let scratch = Scratch::new("layout");
+        let installation = "cpython-3.12.8-linux-x86_64-gnu";
+        let uv_python_dir = scratch.0.join("uv-python");
+        touch(&uv_python_dir.join(installation).join("bin/python3.12"));
+        std::os::unix::fs::symlink(
+            "python3.12",
+            uv_python_dir.join(installation).join("bin/python3"),
+        )
+        .unwrap();
+        // uv links the minor version to the latest patch release.
+        std::os::unix::fs::symlink(
+            installation,
+            uv_python_dir.join("cpython-3.12-linux-x86_64-gnu"),
+        )
+        .unwrap();
+
+        let staging = scratch.0.join(ROOT_DIR);
+        let name = copy_python(
+            &uv_python_dir,
+            &uv_python_dir.join("cpython-3.12-linux-x86_64-gnu/bin/python3"),
+            &staging.join(PYTHON_DIR),
+        )
+        .unwrap();
+        assert_eq!(name, installation);
+        let staged = staging.join(PYTHON_DIR).join(installation).join("bin");
+        assert!(staged.join("python3.12").is_file());
+        assert_eq!(
+            fs::read_link(staged.join("python3")).unwrap(),
+            Path::new("python3.12")
+        );
+
+        let wheel = staging
+            .join(WHEELS_DIR)
+            .join("mmcv-2.1.0+mmai.0123456789ab-cp312-cp312-linux_x86_64.whl");
+        touch(&wheel);
+        touch(&cache::record_path(&wheel));
+        let torch = "torch-2.1.2+cu121-cp312-cp312-linux_x86_64.whl";
+        touch(&staging.join(WHEELS_DIR).join(torch));
+        let bundle = Bundle {
+            archive: scratch.0.join(DEFAULT_BUNDLE_FILE),
+            dir: staging.clone(),
+            scratch: None,
+            info: BundleInfo {
+                created_at: cache::utc_timestamp(),
+                packages: Vec::new(),
+                uv: None,
+                python: Some(name),
+                wheels: vec![cache::file_name(&wheel)],
+                dependencies: vec![torch.to_string()],
+            },
+        };
+
+        // Only the built wheels join the wheelhouse; the rest is installed
+        // from the bundle.
+        let wheelhouse = scratch.0.join("wheelhouse");
+        bundle.import_wheels(&wheelhouse).unwrap();
+        let mut imported = fs::read_dir(&wheelhouse)
+            .unwrap()
+            .map(|entry| cache::file_name(&entry.unwrap().path()))
+            .collect::<Vec<_>>();
+        imported.sort();
+        assert_eq!(
+            imported,
+            [
+                cache::file_name(&cache::record_path(&wheel)),
+                cache::file_name(&wheel)
+            ]
+        );
+
+        let target = scratch.0.join("target-uv-python");
+        import_python(&staging.join(PYTHON_DIR), &target).unwrap();
+        assert!(target.join(installation).join("bin/python3.12").is_file());
+        // An installation uv already has is left alone.
+        import_python(&staging.join(PYTHON_DIR), &target).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_python_uv_does_not_manage() {
+        let scratch = Scratch::new("system-python");
+        let interpreter = scratch.0.join("usr/bin/python3.12");
+        touch(&interpreter);
+        fs::create_dir_all(scratch.0.join("uv-python")).unwrap();
+        let error = copy_python(
+            &scratch.0.join("uv-python"),
+            &interpreter,
+            &scratch.0.join(PYTHON_DIR),
+        )
+        .unwrap_err();
+        assert!(format!("{error:#}").contains("not a uv-managed Python"));
+    }
+
+    #[test]
+    fn downloads_wheels_with_the_bundled_python() {
+        let command = download_command(
+            Path::new("python/cpython-3.12.8-linux-x86_64-gnu/bin/python3"),
+            Path::new("wheels"),
+            &["torch==2.1.2+cu121".to_string()],
+            Some("https://download.pytorch.org/whl/cu121"),
+            Some(Path::new(REQUIREMENTS_FILE)),
+        );
+        let args: Vec<String> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            args.join(" "),
+            "run --no-project --python python/cpython-3.12.8-linux-x86_64-gnu/bin/python3 \
+             --with pip -- python -m pip download --only-binary :all: --dest wheels \
+             --extra-index-url https://download.pytorch.org/whl/cu121 \
+             pip setuptools<81 wheel torch==2.1.2+cu121 -r requirements.txt"
+        );
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::BuildVariant;
+use crate::pipeline::PackageSpec;
+use crate::{App, WHEELHOUSE, query_python};
+
+const RECORD_EXTENSION: &str = "json";
+
+/// The interpreter and torch a wheel is built against, detected once per run.
+#[derive(Debug, Clone)]
+pub struct BuildTarget {
+    pub python_tag: String,
+    pub torch: String,
+    pub variant: String,
+}
+
+impl BuildTarget {
+    pub fn detect(app: &App) -> Result<Self> {
+        let python_tag = query_python(app, "import sys; print('cp%d%d' % sys.version_info[:2])")?
+            .unwrap_or_else(|| format!("cp{}", app.python_version.replace('.', "")));
+
+        let torch_info = query_python(
+            app,
+            "import torch; print(torch.__version__); print(torch.cuda.is_available()); print(torch.version.cuda or '')",
+        )?;
+        // The CUDA line is empty (and trimmed away) for CPU builds of torch.
+        let (torch, gpu_visible, cuda) = match torch_info {
+            Some(info) => {
+                let mut lines = info.lines().map(str::trim);
+                (
+                    lines.next().unwrap_or_default().to_string(),
+                    lines.next() == Some("True"),
+                    lines.next().unwrap_or_default().to_string(),
+                )
+            }
+            None => ("none".to_string(), false, String::new()),
+        };
+
+        // What the extension ops are compiled for: the OpenMMLab setup.py
+        // files build CUDA ops when torch sees a GPU or FORCE_CUDA=1, so an
+        // `auto` build on a machine without a GPU is CPU-only. The requested
+        // variant is kept too, so `auto` and `cuda` wheels never mix.
+        let ops = match app.build_variant {
+            BuildVariant::Cpu => None,
+            _ if cuda.is_empty() => None,
+            BuildVariant::Cuda => Some(format!("cu{}", cuda.replace('.', ""))),
+            BuildVariant::Auto => (gpu_visible
+                || std::env::var("FORCE_CUDA").is_ok_and(|value| value == "1"))
+            .then(|| format!("cu{}", cuda.replace('.', ""))),
+        };
+        let variant = match app.build_variant {
+            BuildVariant::Cpu => "cpu".to_string(),
+            requested => format!("{}-{}", requested.as_str(), ops.as_deref().unwrap_or("cpu")),
+        };
+
+        Ok(Self {
+            python_tag,
+            torch,
+            variant,
+        })
+    }
+}
+
+/// Everything besides name and version that decides whether a cached wheel
+/// can be reused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheKey {
+    #[serde(rename = "python")]
+    pub python_tag: String,
+    pub torch: String,
+    pub variant: String,
+    pub patches: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source: String,
+}
+
+impl CacheKey {
+    pub fn new(target: &BuildTarget, spec: &PackageSpec) -> Result<Self> {
+        Ok(Self {
+            python_tag: target.python_tag.clone(),
+            torch: target.torch.clone(),
+            variant: target.variant.clone(),
+            patches: spec.patch_fingerprint(),
+            source: spec.source_key()?,
+        })
+    }
+
+    fn fields(&self) -> [(&'static str, &str); 5] {
+        [
+            ("python", &self.python_tag),
+            ("torch", &self.torch),
+            ("variant", &self.variant),
+            ("patches", &self.patches),
+            (
+                "source",
+                if self.source.is_empty() {
+                    "not recorded"
+                } else {
+                    &self.source
+                },
+            ),
+        ]
+    }
+
+    /// sha256 of the package version and this key: what the wheel store
+    /// files a build under.
+    pub fn fingerprint(&self, package: &str, version: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{package}\n{version}\n"));
+        for (name, value) in self.fields() {
+            hasher.update(format!("{name}:{value}\n"));
+        }
+        hex(&hasher.finalize())
+    }
+
+    /// Explains how `cached` differs from this (wanted) key.
+    fn mismatch(&self, cached: &CacheKey) -> Option<String> {
+        let differences: Vec<String> = self
+            .fields()
+            .iter()
+            .zip(cached.fields())
+            .filter(|((_, wanted), (_, found))| wanted != found)
+            .map(|((name, wanted), (_, found))| format!("{name} {found} (now {wanted})"))
+            .collect();
+        (!differences.is_empty()).then(|| differences.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceInfo {
+    pub repository: String,
+    #[serde(rename = "ref", alias = "tag")]
+    pub git_ref: String,
+    pub commit: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedPatch {
+    pub kind: String,
+    pub path: String,
+    /// sha256 of the file after patching; of the diff itself for diffs.
+    pub sha256: String,
+}
+
+/// How a wheel in the wheelhouse was produced, written next to it as
+/// `<wheel stem>.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub package: String,
+    pub version: String,
+    pub wheel: String,
+    pub sha256: String,
+    pub cache_key: CacheKey,
+    pub source: SourceInfo,
+    pub patches: Vec<AppliedPatch>,
+    pub compiler: Option<String>,
+    pub build_env: BTreeMap<String, String>,
+    pub built_at: String,
+    pub build_seconds: f64,
+}
+
+impl BuildRecord {
+    pub fn write(&self) -> Result<PathBuf> {
+        let path = record_path(&Path::new(WHEELHOUSE).join(&self.wheel));
+        let json =
+            serde_json::to_string_pretty(self).context("failed to serialize build record")?;
+        fs::write(&path, json + "\n")
+            .with_context(|| format!("failed writing {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+pub enum Lookup {
+    Hit(PathBuf),
+    Miss {
+        reason: String,
+        /// Rejected wheels that the rebuild would overwrite.
+        stale: Vec<PathBuf>,
+    },
+}
+
+/// Finds a reusable wheel from the build records in the wheelhouse. Wheels of
+/// the package version without a record are reported as stale.
+pub fn lookup(spec: &PackageSpec, key: &CacheKey) -> Result<Lookup> {
+    lookup_in(Path::new(WHEELHOUSE), spec, key)
+}
+
+fn lookup_in(wheelhouse: &Path, spec: &PackageSpec, key: &CacheKey) -> Result<Lookup> {
+    let records: Vec<BuildRecord> = read_records(wheelhouse)?
+        .into_iter()
+        .filter(|record| {
+            record.package == spec.name && base_version(&record.version) == spec.version
+        })
+        .collect();
+
+    let mut reasons = Vec::new();
+    let mut stale = Vec::new();
+    for record in &records {
+        let wheel = wheelhouse.join(&record.wheel);
+        if !wheel.is_file() {
+            reasons.push(format!("{} is recorded but missing", record.wheel));
+            stale.push(wheel);
+            continue;
+        }
+        if let Some(reason) = key.mismatch(&record.cache_key) {
+            reasons.push(format!("rejected {}: built for {reason}", record.wheel));
+            if record.cache_key.python_tag == key.python_tag {
+                stale.push(wheel);
+            }
+            continue;
+        }
+        if file_sha256(&wheel)? != record.sha256 {
+            reasons.push(format!(
+                "rejected {}: sha256 differs from its build record",
+                record.wheel
+            ));
+            stale.push(wheel);
+            continue;
+        }
+        return Ok(Lookup::Hit(wheel));
+    }
+
+    let recorded: HashSet<&str> = records.iter().map(|record| record.wheel.as_str()).collect();
+    for wheel in spec.version_wheels_in(wheelhouse)? {
+        let file_name = file_name(&wheel);
+        if !recorded.contains(file_name.as_str()) {
+            reasons.push(format!("rejected {file_name}: no build record"));
+            stale.push(wheel);
+        }
+    }
+
+    if reasons.is_empty() {
+        reasons.push(format!(
+            "no {} {} wheel in the wheelhouse",
+            spec.name, spec.version
+        ));
+    }
+    Ok(Lookup::Miss {
+        reason: reasons.join("; "),
+        stale,
+    })
+}
+
+/// Every parseable build record in the wheelhouse.
+fn read_records(wheelhouse: &Path) -> Result<Vec<BuildRecord>> {
+    let Ok(entries) = fs::read_dir(wheelhouse) else {
+        return Ok(Vec::new());
+    };
+
+    let mut records = Vec::new();
+    for entry in entries {
+        let path = entry.context("failed to list the wheelhouse")?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(RECORD_EXTENSION) {
+            continue;
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("failed reading {}", path.display()))?;
+        // A record that no longer parses just leaves its wheel unrecorded,
+        // which gets it rebuilt.
+        if let Ok(record) = serde_json::from_str(&content) {
+            records.push(record);
+        }
+    }
+    records.sort_by(|a: &BuildRecord, b| a.wheel.cmp(&b.wheel));
+    Ok(records)
+}
+
+/// Strips the PEP 440 local label: `0.10.7+mmai.1a2b` → `0.10.7`.
+fn base_version(version: &str) -> &str {
+    version.split_once('+').map_or(version, |(base, _)| base)
+}
+
+pub fn record_path(wheel: &Path) -> PathBuf {
+    wheel.with_extension(RECORD_EXTENSION)
+}
+
+pub fn remove_wheel(wheel: &Path) -> Result<()> {
+    for path in [wheel.to_path_buf(), record_path(wheel)] {
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove file: {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed reading {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("failed reading {}", path.display()))?;
+    Ok(hex(&hasher.finalize()))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The current time as an RFC 3339 UTC timestamp.
+pub fn utc_timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixes::Catalog;
+    use crate::manifest::Manifest;
+
+    /// A scratch wheelhouse, removed when dropped.
+    struct Wheelhouse(PathBuf);
+
+    impl Wheelhouse {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "mmaction-install-cache-{name}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Adds a wheel and a build record of it made with `key`.
+        fn add(&self, wheel: &str, key: &CacheKey) -> PathBuf {
+            let path = self.0.join(wheel);
+            fs::write(&path, wheel).unwrap();
+            let record = BuildRecord {
+                package: "mmengine".to_string(),
+                version: wheel.split('-').nth(1).unwrap().to_string(),
+                wheel: wheel.to_string(),
+                sha256: file_sha256(&path).unwrap(),
+                cache_key: key.clone(),
+                source: SourceInfo {
+                    repository: "https://github.com/open-mmlab/mmengine".to_string(),
+                    git_ref: "v0.10.7".to_string(),
+                    commit: None,
+                },
+                patches: Vec::new(),
+                compiler: None,
+                build_env: BTreeMap::new(),
+                built_at: utc_timestamp(),
+                build_seconds: 1.0,
+            };
+            fs::write(record_path(&path), serde_json::to_string(&record).unwrap()).unwrap();
+            path
+        }
+
+        fn lookup(&self, key: &CacheKey) -> Lookup {
+            lookup_in(&self.0, &spec(), key).unwrap()
+        }
+    }
+
+    impl Drop for Wheelhouse {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn spec() -> PackageSpec {
+        let manifest = Manifest::bundled().unwrap();
+        let mmengine = manifest
+            .packages
+            .iter()
+            .find(|package| package.name == "mmengine")
+            .unwrap();
+        PackageSpec::from_manifest(mmengine, &Catalog::default(), Path::new("no-patches")).unwrap()
+    }
+
+    fn key() -> CacheKey {
+        CacheKey {
+            python_tag: "cp311".to_string(),
+            torch: "2.4.1+cu121".to_string(),
+            variant: "auto-cu121".to_string(),
+            patches: "1a2b3c".to_string(),
+            source: "https://github.com/open-mmlab/mmengine".to_string(),
+        }
+    }
+
+    const WHEEL: &str = "mmengine-0.10.7+mmai.1a2b3c-py3-none-any.whl";
+
+    fn stale(lookup: Lookup) -> (String, Vec<PathBuf>) {
+        match lookup {
+            Lookup::Hit(wheel) => panic!("unexpected hit: {}", wheel.display()),
+            Lookup::Miss { reason, stale } => (reason, stale),
+        }
+    }
+
+    #[test]
+    fn hits_a_wheel_built_with_the_same_key() {
+        let wheelhouse = Wheelhouse::new("hit");
+        let wheel = wheelhouse.add(WHEEL, &key());
+        assert!(matches!(wheelhouse.lookup(&key()), Lookup::Hit(found) if found == wheel));
+    }
+
+    #[test]
+    fn rejects_a_wheel_built_against_another_torch_as_stale() {
+        let wheelhouse = Wheelhouse::new("torch");
+        let wheel = wheelhouse.add(WHEEL, &key());
+        let wanted = CacheKey {
+            torch: "2.5.1+cu121".to_string(),
+            ..key()
+        };
+        let (reason, stale) = stale(wheelhouse.lookup(&wanted));
+        assert!(
+            reason.contains("torch 2.4.1+cu121 (now 2.5.1+cu121)"),
+            "{reason}"
+        );
+        assert_eq!(stale, [wheel]);
+    }
+
+    #[test]
+    fn rejects_a_wheel_with_another_patch_fingerprint_as_stale() {
+        let wheelhouse = Wheelhouse::new("patches");
+        let wheel = wheelhouse.add(WHEEL, &key());
+        let wanted = CacheKey {
+            patches: "4d5e6f".to_string(),
+            ..key()
+        };
+        let (reason, stale) = stale(wheelhouse.lookup(&wanted));
+        assert!(reason.contains("patches 1a2b3c (now 4d5e6f)"), "{reason}");
+        assert_eq!(stale, [wheel]);
+    }
+
+    #[test]
+    fn keeps_wheels_of_another_python() {
+        let wheelhouse = Wheelhouse::new("python");
+        wheelhouse.add(WHEEL, &key());
+        let wanted = CacheKey {
+            python_tag: "cp312".to_string(),
+            ..key()
+        };
+        let (reason, stale) = stale(wheelhouse.lookup(&wanted));
+        assert!(reason.contains("python cp311 (now cp312)"), "{reason}");
+        assert!(stale.is_empty());
+    }
+
+    #[test]
+    fn rejects_modified_and_unrecorded_wheels() {
+        let wheelhouse = Wheelhouse::new("modified");
+        let wheel = wheelhouse.add(WHEEL, &key());
+        fs::write(&wheel, "rebuilt by hand").unwrap();
+        let unrecorded = wheelhouse.0.join("mmengine-0.10.7-py3-none-any.whl");
+        fs::write(&unrecorded, "").unwrap();
+
+        let (reason, stale) = stale(wheelhouse.lookup(&key()));
+        assert!(reason.contains("sha256 differs"), "{reason}");
+        assert!(reason.contains("no build record"), "{reason}");
+        assert_eq!(stale, [wheel, unrecorded]);
+    }
+
+    #[test]
+    fn misses_without_wheels_of_the_version() {
+        let wheelhouse = Wheelhouse::new("miss");
+        wheelhouse.add("mmengine-0.10.5-py3-none-any.whl", &key());
+        let (reason, stale) = stale(wheelhouse.lookup(&key()));
+        assert_eq!(reason, "no mmengine 0.10.7 wheel in the wheelhouse");
+        assert!(stale.is_empty());
+    }
+
+    #[test]
+    fn explains_every_field_that_differs() {
+        let wanted = CacheKey {
+            variant: "cpu".to_string(),
+            source: String::new(),
+            ..key()
+        };
+        assert_eq!(key().mismatch(&key()), None);
+        assert_eq!(
+            wanted.mismatch(&key()).unwrap(),
+            "variant auto-cu121 (now cpu), source https://github.com/open-mmlab/mmengine (now not recorded)"
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use console::style;
+
+use crate::cache;
+use crate::manifest::GitRef;
+use crate::patch::PatchStatus;
+use crate::pipeline::{PackageSpec, Pipeline};
+use crate::{
+    App, CHECK_DIR, Steps, WHEELHOUSE, bundle, check_compatibility, doctor, ensure_pip_tooling,
+    ensure_uv, ensure_venv, ensure_wheelhouse, offline, patch, print_header, print_table,
+    print_warnings, query_python, remove_dir_if_exists, run_uv_sync, sources, torch,
+};
+
+pub fn doctor(app: &App) -> Result<()> {
+    print_header(app);
+    println!();
+
+    let checks = doctor::run_checks(app, true)?;
+    doctor::print_report(&checks);
+
+    let failed = checks.iter().filter(|check| check.failed()).count();
+    if failed > 0 {
+        bail!("doctor found {failed} failing check(s)");
+    }
+    app.finish("Environment looks good.");
+    Ok(())
+}
+
+pub fn install(app: &App) -> Result<()> {
+    let total_steps = 8
+        + app.packages.len()
+        + usize::from(app.purge)
+        + usize::from(app.offline)
+        + 2 * usize::from(app.bundle.is_some());
+    let mut steps = Steps::new(total_steps, app.verbose());
+
+    print_header(app);
+
+    if let Some(bundle) = &app.bundle {
+        steps.run("Importing bundle", || bundle.import(app))?;
+    }
+
+    if app.offline {
+        steps.run("Checking offline caches", || offline::check(app))?;
+    }
+
+    if app.purge {
+        steps.run("Purging mmaction cache directories", || {
+            remove_cached(app, true, true, &app.packages)
+        })?;
+    }
+
+    prepare_build_env(app, &mut steps)?;
+
+    for package in &app.packages {
+        let mut outcomes = Vec::new();
+        let result = steps.run(&format!("Building/installing {}", package.name), || {
+            Pipeline::new(app, package).run(&mut outcomes)
+        });
+        patch::print_summary(&package.name, &outcomes);
+        result?;
+    }
+
+    if let Some(bundle) = &app.bundle {
+        steps.run("Installing bundled dependencies", || {
+            bundle.install_dependencies(app)
+        })?;
+    }
+    steps.run_with("Running uv sync", true, || run_uv_sync(app))?;
+
+    app.finish("Setup completed successfully.");
+    Ok(())
+}
+
+pub fn build(app: &App) -> Result<()> {
+    let total_steps = 7 + app.packages.len() + usize::from(app.offline);
+    let mut steps = Steps::new(total_steps, app.verbose());
+
+    print_header(app);
+    if app.offline {
+        steps.run("Checking offline caches", || offline::check(app))?;
+    }
+    prepare_build_env(app, &mut steps)?;
+
+    for package in &app.packages {
+        let mut outcomes = Vec::new();
+        let result = steps.run(&format!("Building {}", package.name), || {
+            Pipeline::new(app, package).build(&mut outcomes)
+        });
+        patch::print_summary(&package.name, &outcomes);
+        result?;
+    }
+
+    app.finish(&format!("Wheels are up to date in {WHEELHOUSE}."));
+    Ok(())
+}
+
+/// Wheelhouse, uv, venv, pip tooling, torch, the compatibility check and the
+/// build preflight: seven steps shared by `install` and `build`.
+fn prepare_build_env(app: &App, steps: &mut Steps) -> Result<()> {
+    steps.run("Ensuring wheelhouse directory", || ensure_wheelhouse(app))?;
+    steps.run("Ensuring uv availability", || ensure_uv(app))?;
+    steps.run("Ensuring Python virtual environment", || ensure_venv(app))?;
+    steps.run("Ensuring pip tooling", || ensure_pip_tooling(app))?;
+
+    let mut torch_warnings = Vec::new();
+    steps.run("Installing the project's torch", || {
+        torch::ensure(app, &mut torch_warnings)
+    })?;
+    print_warnings(&torch_warnings);
+
+    let mut compat_warnings = Vec::new();
+    steps.run("Checking version compatibility", || {
+        check_compatibility(app, &mut compat_warnings)
+    })?;
+    print_warnings(&compat_warnings);
+
+    let mut preflight_warnings = Vec::new();
+    steps.run("Running build preflight checks", || {
+        doctor::preflight(app, &mut preflight_warnings)
+    })?;
+    print_warnings(&preflight_warnings);
+    Ok(())
+}
+
+pub fn status(app: &App) -> Result<()> {
+    print_header(app);
+
+    let installed = installed_versions(app)?;
+    let rows: Vec<[String; 5]> = app
+        .packages
+        .iter()
+        .map(|package| {
+            let wheels = wheel_versions(package)?;
+            let installed = installed
+                .iter()
+                .find(|(name, _)| name == &package.name)
+                .map(|(_, version)| version.clone())
+                .unwrap_or_else(|| "-".to_string());
+            let source = if package.source_dir.is_dir() {
+                package.source_dir.display().to_string()
+            } else {
+                "-".to_string()
+            };
+            Ok([
+                package.name.clone(),
+                package.build_version(),
+                if wheels.is_empty() {
+                    "-".to_string()
+                } else {
+                    wheels.join(", ")
+                },
+                installed,
+                source,
+            ])
+        })
+        .collect::<Result<_>>()?;
+
+    println!();
+    print_table(
+        ["Package", "Selected", "Wheels", "Installed", "Source"],
+        &rows,
+        &[],
+    );
+
+    if !app.python_bin().exists() {
+        println!();
+        println!(
+            "{} {}",
+            style("•").cyan(),
+            style(format!(
+                "No virtual environment at {}; installed versions are unknown",
+                app.venv_dir.display()
+            ))
+            .dim()
+        );
+    }
+    Ok(())
+}
+
+fn wheel_versions(package: &PackageSpec) -> Result<Vec<String>> {
+    let prefix = format!("{}-", package.wheel_name());
+    let mut versions: Vec<String> = package
+        .wheelhouse_wheels()?
+        .iter()
+        .filter_map(|wheel| {
+            let file_name = wheel.file_name()?.to_str()?;
+            let rest = file_name.strip_prefix(&prefix)?;
+            Some(rest.split('-').next()?.to_string())
+        })
+        .collect();
+    versions.sort();
+    versions.dedup();
+    Ok(versions)
+}
+
+fn installed_versions(app: &App) -> Result<Vec<(String, String)>> {
+    let names: Vec<String> = app
+        .packages
+        .iter()
+        .map(|package| format!("{:?}", package.name))
+        .collect();
+    let script = format!(
+        "import importlib.metadata as m\nfor name in [{}]:\n    try:\n        print(name, m.version(name))\n    except m.PackageNotFoundError:\n        pass",
+        names.join(", ")
+    );
+
+    let Some(output) = query_python(app, &script)? else {
+        return Ok(Vec::new());
+    };
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let (name, version) = line.split_once(' ')?;
+            Some((name.to_string(), version.to_string()))
+        })
+        .collect())
+}
+
+pub fn clean(app: &App, wheels: bool, sources: bool, names: &[String]) -> Result<()> {
+    let (wheels, sources) = if wheels || sources {
+        (wheels, sources)
+    } else {
+        (true, true)
+    };
+
+    let packages = select_packages(app, names)?;
+
+    let mut steps = Steps::new(1, app.verbose());
+    print_header(app);
+    steps.run("Removing cached wheels and sources", || {
+        remove_cached(app, wheels, sources, &packages)
+    })?;
+
+    app.finish("Clean completed successfully.");
+    Ok(())
+}
+
+/// The named manifest packages, or all of them when no names are given.
+fn select_packages(app: &App, names: &[String]) -> Result<Vec<PackageSpec>> {
+    if names.is_empty() {
+        return Ok(app.packages.clone());
+    }
+    names
+        .iter()
+        .map(|name| {
+            app.packages
+                .iter()
+                .find(|package| &package.name == name)
+                .cloned()
+                .with_context(|| format!("unknown package {name}"))
+        })
+        .collect()
+}
+
+/// Checks that the configured patches still apply to an upstream tag and
+/// shows what they would change, without building or installing anything.
+pub fn patch_check(app: &App, names: &[String], tag: Option<&str>) -> Result<()> {
+    let mut packages = select_packages(app, names)?;
+    if let Some(tag) = tag {
+        let [package] = packages.as_mut_slice() else {
+            bail!("--tag needs exactly one --package");
+        };
+        package.version = tag.strip_prefix('v').unwrap_or(tag).to_string();
+        package.git_ref = GitRef::Tag(tag.to_string());
+        package.commit = None;
+        package.fixes = app.catalog.matching(&package.name, &package.version);
+    }
+    packages.retain(|package| {
+        !package.patches.is_empty() || !package.diffs.is_empty() || !package.fixes.is_empty()
+    });
+    // Checks get their own checkout per tag, so the build sources stay as
+    // they are.
+    for package in &mut packages {
+        package.source_dir = Path::new(CHECK_DIR).join(format!(
+            "{}-{}",
+            package.name,
+            package.git_ref.name().replace('/', "_")
+        ));
+    }
+
+    let mut steps = Steps::new(packages.len(), app.verbose());
+    print_header(app);
+
+    let mut not_applied = 0;
+    for package in &packages {
+        let mut outcomes = Vec::new();
+        let mut changes = Vec::new();
+        let result = steps.run(
+            &format!(
+                "Checking {} patches against {}",
+                package.name, package.git_ref
+            ),
+            || {
+                changes = Pipeline::new(app, package).check(&mut outcomes)?;
+                Ok(())
+            },
+        );
+        patch::print_summary(&package.name, &outcomes);
+        patch::print_changes(&changes);
+        result?;
+        not_applied += outcomes
+            .iter()
+            .filter(|outcome| {
+                !outcome.status.is_in_place() && outcome.status != PatchStatus::NotNeeded
+            })
+            .count();
+    }
+
+    if not_applied > 0 {
+        bail!("{not_applied} patch(es) would not apply");
+    }
+    app.finish(if packages.is_empty() {
+        "No selected package has patches to check."
+    } else {
+        "Every patch applies."
+    });
+    Ok(())
+}
+
+/// Fetches the selected packages' sources and writes them, with the patches,
+/// the fix catalog and uv, to a bundle `install --from-bundle` can use
+/// without network access.
+pub fn prefetch(app: &App, output: &Path, with_wheels: bool) -> Result<()> {
+    let mut steps = Steps::new(2 + app.packages.len(), app.verbose());
+    print_header(app);
+
+    steps.run("Ensuring uv availability", || ensure_uv(app))?;
+    for package in &app.packages {
+        steps.run(
+            &format!("Fetching {} {}", package.name, package.git_ref),
+            || {
+                // Offline, a cached tree is used as is; without one the ref
+                // is checked out of the git cache.
+                let _lock = sources::lock(app, package)?;
+                match sources::cached(package)? {
+                    Some(stamp) if app.offline => {
+                        sources::verify_commit(package, stamp.commit.as_deref())
+                    }
+                    _ => sources::fetch(app, package).map(drop),
+                }
+            },
+        )?;
+    }
+    if app.offline {
+        print_warnings(&[
+            "--offline: the bundle carries no Python, torch or dependency wheels; the target needs them in uv's cache or a prepared --venv"
+                .to_string(),
+        ]);
+    }
+    steps.run("Writing bundle", || bundle::write(app, output, with_wheels))?;
+
+    app.finish(&format!("Bundle written to {}.", output.display()));
+    Ok(())
+}
+
+/// Removes package wheels and/or source trees. When every manifest package
+/// is selected the whole wheelhouse goes, including stray wheels.
+fn remove_cached(app: &App, wheels: bool, sources: bool, packages: &[PackageSpec]) -> Result<()> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+
+    if wheels {
+        if packages.len() == app.packages.len() {
+            dirs.push(PathBuf::from(WHEELHOUSE));
+        } else {
+            for package in packages {
+                files.extend(package.wheelhouse_wheels()?);
+            }
+        }
+    }
+    if sources {
+        dirs.extend(packages.iter().map(|package| package.source_dir.clone()));
+        if packages.len() == app.packages.len() {
+            dirs.push(PathBuf::from(CHECK_DIR));
+        }
+    }
+
+    for dir in dirs {
+        if app.dry_run {
+            if dir.exists() {
+                app.plan(&format!("would remove {}", dir.display()));
+            }
+            continue;
+        }
+        remove_dir_if_exists(&dir)?;
+    }
+    for file in files {
+        if app.dry_run {
+            app.plan(&format!("would remove {}", file.display()));
+            continue;
+        }
+        cache::remove_wheel(&file)?;
+    }
+    Ok(())
+}
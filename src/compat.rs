@@ -0,0 +1,480 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use anyhow::{Result, bail};
+
+use crate::pipeline::PackageSpec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One row of the compatibility table: `package` versions matching `when`
+/// need `dependency` (a package name, `torch` or `python`) within `requires`.
+struct Rule {
+    package: &'static str,
+    when: &'static str,
+    dependency: &'static str,
+    requires: &'static str,
+    severity: Severity,
+    reason: &'static str,
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        package: "mmaction2",
+        when: ">=1.0.0",
+        dependency: "mmcv",
+        requires: ">=2.0.0rc4,<2.2.0",
+        severity: Severity::Error,
+        reason: "mmaction/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmaction2",
+        when: ">=1.0.0",
+        dependency: "mmengine",
+        requires: ">=0.7.1,<1.0.0",
+        severity: Severity::Error,
+        reason: "mmaction/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmdet",
+        when: ">=3.0.0",
+        dependency: "mmcv",
+        requires: ">=2.0.0rc4,<2.2.0",
+        severity: Severity::Error,
+        reason: "mmdet/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmdet",
+        when: ">=3.0.0",
+        dependency: "mmengine",
+        requires: ">=0.7.1,<1.0.0",
+        severity: Severity::Error,
+        reason: "mmdet/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmpose",
+        when: ">=1.0.0",
+        dependency: "mmcv",
+        requires: ">=2.0.0,<2.2.0",
+        severity: Severity::Error,
+        reason: "mmpose/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmpose",
+        when: ">=1.0.0",
+        dependency: "mmengine",
+        requires: ">=0.6.0,<1.0.0",
+        severity: Severity::Error,
+        reason: "mmpose/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmpretrain",
+        when: ">=1.0.0",
+        dependency: "mmcv",
+        requires: ">=2.0.0,<2.4.0",
+        severity: Severity::Error,
+        reason: "mmpretrain/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmpretrain",
+        when: ">=1.0.0",
+        dependency: "mmengine",
+        requires: ">=0.8.3,<1.0.0",
+        severity: Severity::Error,
+        reason: "mmpretrain/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmsegmentation",
+        when: ">=1.0.0",
+        dependency: "mmcv",
+        requires: ">=2.0.0rc4,<2.2.0",
+        severity: Severity::Error,
+        reason: "mmseg/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmsegmentation",
+        when: ">=1.0.0",
+        dependency: "mmengine",
+        requires: ">=0.5.0,<1.0.0",
+        severity: Severity::Error,
+        reason: "mmseg/__init__.py asserts this range at import time",
+    },
+    Rule {
+        package: "mmcv",
+        when: ">=2.0.0,<2.2.0",
+        dependency: "torch",
+        requires: ">=1.8.0,<2.2.0",
+        severity: Severity::Warning,
+        reason: "upstream only tests these torch releases; newer torch may fail to compile the ops",
+    },
+    Rule {
+        package: "mmcv",
+        when: ">=2.2.0",
+        dependency: "torch",
+        requires: ">=1.8.0,<2.5.0",
+        severity: Severity::Warning,
+        reason: "upstream only tests these torch releases; newer torch may fail to compile the ops",
+    },
+    Rule {
+        package: "mmcv",
+        when: ">=2.0.0",
+        dependency: "python",
+        requires: ">=3.7",
+        severity: Severity::Error,
+        reason: "setup.py declares python_requires>=3.7",
+    },
+    Rule {
+        package: "mmengine",
+        when: ">=0.10.0",
+        dependency: "python",
+        requires: ">=3.7",
+        severity: Severity::Error,
+        reason: "setup.py declares python_requires>=3.7",
+    },
+    Rule {
+        package: "mmengine",
+        when: ">=0.10.0",
+        dependency: "torch",
+        requires: ">=1.6.0",
+        severity: Severity::Error,
+        reason: "mmengine 0.10 uses torch APIs introduced in 1.6",
+    },
+];
+
+/// A failed constraint, formatted as a single human-readable line.
+pub struct Violation {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Checks the selected packages against each other and against the torch
+/// and Python versions found in the venv. Missing torch/Python versions skip
+/// the rules that need them, and so do versions that are not PEP 440, with a
+/// warning.
+pub fn check(
+    packages: &[PackageSpec],
+    torch: Option<&str>,
+    python: Option<&str>,
+) -> Result<Vec<Violation>> {
+    let mut violations = Vec::new();
+    for (name, version) in packages
+        .iter()
+        .map(|package| (package.name.as_str(), Some(package.version.as_str())))
+        .chain([("torch", torch), ("python", python)])
+    {
+        if let Some(version) = version
+            && Version::parse(version).is_err()
+        {
+            violations.push(Violation {
+                severity: Severity::Warning,
+                message: format!(
+                    "{name} {version} is not a PEP 440 version; skipped {}",
+                    if matches!(name, "torch" | "python") {
+                        "the compatibility rules that need it"
+                    } else {
+                        "its compatibility rules and known fixes"
+                    }
+                ),
+            });
+        }
+    }
+
+    for rule in RULES {
+        let Some(package) = packages.iter().find(|package| package.name == rule.package) else {
+            continue;
+        };
+        let Ok(package_version) = Version::parse(&package.version) else {
+            continue;
+        };
+        if !Specifier::parse(rule.when)?.matches(&package_version) {
+            continue;
+        }
+
+        let dependency_version = match rule.dependency {
+            "torch" => torch.map(str::to_string),
+            "python" => python.map(str::to_string),
+            name => packages
+                .iter()
+                .find(|package| package.name == name)
+                .map(|package| package.version.clone()),
+        };
+        let Some(dependency_version) = dependency_version else {
+            continue;
+        };
+
+        let Ok(parsed) = Version::parse(&dependency_version) else {
+            continue;
+        };
+        if Specifier::parse(rule.requires)?.matches(&parsed) {
+            continue;
+        }
+
+        violations.push(Violation {
+            severity: rule.severity,
+            message: format!(
+                "{} {} requires {} {}, found {} {} ({})",
+                package.name,
+                package.version,
+                rule.dependency,
+                rule.requires,
+                rule.dependency,
+                dependency_version,
+                rule.reason
+            ),
+        });
+    }
+
+    Ok(violations)
+}
+
+/// A PEP 440 version reduced to what the compatibility table needs:
+/// release numbers, pre/post release markers and no local label.
+#[derive(Debug, Clone)]
+pub struct Version {
+    release: Vec<u64>,
+    // (rank, number): dev < a < b < rc < final < post
+    suffix: (u8, u64),
+}
+
+impl Version {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let trimmed = raw.trim().trim_start_matches('v');
+        let public = trimmed.split('+').next().unwrap_or_default();
+        let split_at = public
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(public.len());
+        let (release_part, suffix_part) = public.split_at(split_at);
+
+        let release = release_part
+            .trim_end_matches('.')
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<std::result::Result<Vec<_>, _>>();
+        let Ok(release) = release else {
+            bail!("invalid version: {raw}");
+        };
+
+        let suffix_part = suffix_part.trim_start_matches(['.', '-', '_']);
+        let suffix = if suffix_part.is_empty() {
+            (4, 0)
+        } else {
+            let label_end = suffix_part
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(suffix_part.len());
+            let (label, number) = suffix_part.split_at(label_end);
+            let number = number.parse::<u64>().unwrap_or(0);
+            let rank = match label.to_ascii_lowercase().as_str() {
+                "dev" => 0,
+                "a" | "alpha" => 1,
+                "b" | "beta" => 2,
+                "rc" | "c" | "pre" => 3,
+                "post" => 5,
+                _ => bail!("invalid version: {raw}"),
+            };
+            (rank, number)
+        };
+
+        Ok(Self { release, suffix })
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.release.len().max(other.release.len());
+        for index in 0..len {
+            let left = self.release.get(index).copied().unwrap_or(0);
+            let right = other.release.get(index).copied().unwrap_or(0);
+            match left.cmp(&right) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        self.suffix.cmp(&other.suffix)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A comma-separated list of `>=`, `<=`, `>`, `<`, `==` and `!=` clauses.
+pub struct Specifier {
+    clauses: Vec<(&'static str, Version)>,
+}
+
+impl Specifier {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let mut clauses = Vec::new();
+        for clause in raw.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let Some(op) = [">=", "<=", "==", "!=", ">", "<"]
+                .into_iter()
+                .find(|op| clause.starts_with(op))
+            else {
+                bail!("invalid version specifier: {raw}");
+            };
+            clauses.push((op, Version::parse(&clause[op.len()..])?));
+        }
+        Ok(Self { clauses })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.clauses.iter().all(|(op, bound)| match *op {
+            ">=" => version >= bound,
+            "<=" => version <= bound,
+            ">" => version > bound,
+            "<" => version < bound,
+            "==" => version == bound,
+            "!=" => version != bound,
+            _ => unreachable!("operators are restricted by Specifier::parse"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(raw: &str) -> Version {
+        Version::parse(raw).unwrap()
+    }
+
+    #[test]
+    fn orders_release_numbers_numerically() {
+        assert!(version("2.10.0") > version("2.9.1"));
+        assert_eq!(version("2.1"), version("2.1.0"));
+        assert_eq!(version("v0.10.7"), version("0.10.7"));
+    }
+
+    #[test]
+    fn orders_pre_and_post_releases_around_the_final_release() {
+        let ordered = [
+            "2.0.0.dev1",
+            "2.0.0a1",
+            "2.0.0b2",
+            "2.0.0rc3",
+            "2.0.0rc4",
+            "2.0.0",
+            "2.0.0.post1",
+            "2.0.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{pair:?}");
+        }
+        assert_eq!(version("1.0.0-rc1"), version("1.0.0rc1"));
+        assert_eq!(version("1.0.0alpha2"), version("1.0.0a2"));
+    }
+
+    #[test]
+    fn ignores_local_labels() {
+        assert_eq!(version("2.1.0+mmai.1a2b"), version("2.1.0"));
+        assert_eq!(version("2.4.1+cu121"), version("2.4.1+cpu"));
+        assert!(version("2.1.0+mmai.1a2b") < version("2.1.1"));
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for raw in ["", "x.y", "1.0.0zeta", "1..0"] {
+            assert!(Version::parse(raw).is_err(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn matches_every_clause_of_a_specifier() {
+        let range = Specifier::parse(">=2.0.0rc4, <2.2.0").unwrap();
+        assert!(range.matches(&version("2.0.0rc4")));
+        assert!(range.matches(&version("2.1.0+mmai.abc")));
+        assert!(!range.matches(&version("2.0.0rc3")));
+        assert!(!range.matches(&version("2.2.0")));
+
+        let excluded = Specifier::parse("!=1.3.0,>1.0").unwrap();
+        assert!(excluded.matches(&version("1.2")));
+        assert!(!excluded.matches(&version("1.3.0")));
+        assert!(!excluded.matches(&version("1.0")));
+        assert!(Specifier::parse("").unwrap().matches(&version("0.1")));
+        assert!(Specifier::parse("~=1.0").is_err());
+    }
+
+    fn specs(versions: &[(&str, &str)]) -> Vec<PackageSpec> {
+        let manifest = crate::manifest::Manifest::bundled().unwrap();
+        manifest
+            .packages
+            .iter()
+            .filter(|package| !package.optional)
+            .map(|package| {
+                let mut package = package.clone();
+                if let Some((_, version)) = versions.iter().find(|(name, _)| *name == package.name)
+                {
+                    package.version = version.to_string();
+                }
+                PackageSpec::from_manifest(
+                    &package,
+                    &crate::fixes::Catalog::default(),
+                    std::path::Path::new("no-patches"),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_incompatible_versions() {
+        let violations =
+            check(&specs(&[("mmcv", "2.2.0")]), Some("2.4.1"), Some("3.11.9")).unwrap();
+        let errors: Vec<String> = violations
+            .iter()
+            .filter(|violation| violation.severity == Severity::Error)
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with("mmaction2 1.2.0 requires mmcv >=2.0.0rc4,<2.2.0"));
+        assert!(
+            check(&specs(&[]), Some("2.1.2"), Some("3.11.9"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn warns_about_versions_that_are_not_pep_440() {
+        let violations = check(
+            &specs(&[("mmcv", "2.1.0-12-g0123abc")]),
+            Some("nightly"),
+            Some("3.11.9"),
+        )
+        .unwrap();
+        let messages: Vec<String> = violations.iter().map(ToString::to_string).collect();
+        assert!(
+            violations
+                .iter()
+                .all(|violation| violation.severity == Severity::Warning),
+            "{messages:?}"
+        );
+        assert_eq!(
+            messages,
+            [
+                "mmcv 2.1.0-12-g0123abc is not a PEP 440 version; skipped its compatibility rules and known fixes",
+                "torch nightly is not a PEP 440 version; skipped the compatibility rules that need it",
+            ]
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::manifest::{Manifest, PackageManifest};
+
+pub const PYPROJECT_FILE: &str = "pyproject.toml";
+const TOOL_TABLE: &str = "mmaction-install";
+
+/// Installer defaults read from `[tool.mmaction-install]` in pyproject.toml.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub venv: Option<PathBuf>,
+    pub debug: Option<bool>,
+    /// Ignored with a warning: a committed `purge = true` would wipe the
+    /// wheelhouse on every teammate's install. `--purge` only.
+    pub purge: Option<bool>,
+    pub manifest: Option<PathBuf>,
+    pub python: Option<String>,
+    pub build_variant: Option<BuildVariant>,
+    pub strict_patches: Option<bool>,
+    pub offline: Option<bool>,
+    pub wheel_store: Option<PathBuf>,
+    #[serde(default)]
+    pub with: Vec<String>,
+    #[serde(default)]
+    pub versions: BTreeMap<String, String>,
+    #[serde(default)]
+    pub sources: BTreeMap<String, SourceOverride>,
+    #[serde(default)]
+    pub extra_packages: Vec<PackageManifest>,
+}
+
+/// Replaces where a manifest package's source comes from: a git repository
+/// (with fallback mirrors), a local tree or a source archive, and which ref
+/// or commit of it is built.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SourceOverride {
+    pub repository: Option<String>,
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    pub path: Option<String>,
+    pub tarball: Option<String>,
+    pub tag: Option<String>,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildVariant {
+    /// Let each package's setup.py detect CUDA from the installed torch
+    #[default]
+    Auto,
+    /// Build CPU-only extensions even when a GPU is visible
+    Cpu,
+    /// Force CUDA extensions even when no GPU is visible at build time
+    Cuda,
+}
+
+impl BuildVariant {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BuildVariant::Auto => "auto",
+            BuildVariant::Cpu => "cpu",
+            BuildVariant::Cuda => "cuda",
+        }
+    }
+
+    pub fn build_env(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            BuildVariant::Auto => &[],
+            BuildVariant::Cpu => &[("FORCE_CUDA", "0"), ("CUDA_VISIBLE_DEVICES", "")],
+            BuildVariant::Cuda => &[("FORCE_CUDA", "1")],
+        }
+    }
+}
+
+impl SourceOverride {
+    fn apply_to(&self, package: &mut PackageManifest) -> Result<()> {
+        let kinds = [
+            self.repository.is_some(),
+            self.path.is_some(),
+            self.tarball.is_some(),
+        ]
+        .iter()
+        .filter(|set| **set)
+        .count();
+        let sets_ref = self.tag.is_some() || self.branch.is_some() || self.commit.is_some();
+        if kinds > 1 {
+            bail!("set only one of repository, path and tarball");
+        }
+        if kinds == 0 && self.mirrors.is_empty() && !sets_ref {
+            bail!("set a repository, path, tarball, mirrors, tag, branch or commit");
+        }
+
+        if kinds == 1 {
+            if let Some(repository) = &self.repository {
+                package.repository = repository.clone();
+            }
+            package.mirrors = self.mirrors.clone();
+            package.path = self.path.clone();
+            package.tarball = self.tarball.clone();
+        } else if !self.mirrors.is_empty() {
+            // Only mirrors: extra fallbacks for the manifest's repository.
+            package.mirrors = self.mirrors.clone();
+        }
+        if sets_ref {
+            package.tag = self.tag.clone();
+            package.branch = self.branch.clone();
+            package.commit = self.commit.clone();
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Returns the config and whether a `[tool.mmaction-install]` table was found.
+    pub fn load() -> Result<(Self, bool)> {
+        let path = Path::new(PYPROJECT_FILE);
+        if !path.is_file() {
+            return Ok((Self::default(), false));
+        }
+
+        let content =
+            fs::read_to_string(path).with_context(|| format!("failed reading {PYPROJECT_FILE}"))?;
+        let document: toml::Table =
+            toml::from_str(&content).with_context(|| format!("invalid {PYPROJECT_FILE}"))?;
+
+        let Some(table) = document
+            .get("tool")
+            .and_then(|tool| tool.get(TOOL_TABLE))
+            .cloned()
+        else {
+            return Ok((Self::default(), false));
+        };
+
+        let config: Config = table
+            .try_into()
+            .with_context(|| format!("invalid [tool.{TOOL_TABLE}] in {PYPROJECT_FILE}"))?;
+        Ok((config, true))
+    }
+
+    /// Adds the extra packages to the manifest, then applies version pins and
+    /// source overrides to all of them.
+    pub fn apply_to_manifest(&self, manifest: &mut Manifest) -> Result<()> {
+        manifest
+            .packages
+            .extend(self.extra_packages.iter().cloned());
+
+        for (name, version) in &self.versions {
+            let Some(package) = manifest.packages.iter_mut().find(|p| &p.name == name) else {
+                bail!("[tool.{TOOL_TABLE}] versions: unknown package {name} (not in the manifest)");
+            };
+            package.set_version(version);
+        }
+
+        for (name, source) in &self.sources {
+            let Some(package) = manifest.packages.iter_mut().find(|p| &p.name == name) else {
+                bail!("[tool.{TOOL_TABLE}] sources: unknown package {name} (not in the manifest)");
+            };
+            source
+                .apply_to(package)
+                .with_context(|| format!("invalid [tool.{TOOL_TABLE}] sources.{name}"))?;
+        }
+        manifest
+            .validate()
+            .with_context(|| format!("invalid [tool.{TOOL_TABLE}] extra-packages or sources"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn package<'a>(manifest: &'a Manifest, name: &str) -> &'a PackageManifest {
+        manifest.packages.iter().find(|p| p.name == name).unwrap()
+    }
+
+    #[test]
+    fn pins_versions_and_sources_of_extra_packages() {
+        let config = config(
+            r#"
+            versions = { mmaction2 = "1.1.0", mmagic = "1.2.0" }
+
+            [sources.mmagic]
+            branch = "main"
+
+            [[extra-packages]]
+            name = "mmagic"
+            version = "1.0.0"
+            repository = "https://github.com/open-mmlab/mmagic.git"
+            tag = "v1.0.0"
+            "#,
+        );
+        let mut manifest = Manifest::bundled().unwrap();
+        config.apply_to_manifest(&mut manifest).unwrap();
+
+        assert_eq!(package(&manifest, "mmaction2").version, "1.1.0");
+        let extra = package(&manifest, "mmagic");
+        assert_eq!(extra.version, "1.2.0");
+        assert_eq!(extra.tag, None);
+        assert_eq!(extra.branch.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn rejects_pins_for_unknown_packages() {
+        let mut manifest = Manifest::bundled().unwrap();
+        let error = config(r#"versions = { mmagic = "1.2.0" }"#)
+            .apply_to_manifest(&mut manifest)
+            .unwrap_err();
+        assert!(error.to_string().contains("unknown package mmagic"));
+    }
+
+    #[test]
+    fn replaces_the_source_and_keeps_the_ref() {
+        let mut manifest = Manifest::bundled().unwrap();
+        let mmcv = manifest
+            .packages
+            .iter_mut()
+            .find(|p| p.name == "mmcv")
+            .unwrap();
+        mmcv.tag = Some("v2.1.0".to_string());
+        mmcv.mirrors = vec!["https://gitee.com/open-mmlab/mmcv.git".to_string()];
+
+        let source = SourceOverride {
+            path: Some("../mmcv".to_string()),
+            ..Default::default()
+        };
+        source.apply_to(mmcv).unwrap();
+        assert_eq!(mmcv.path.as_deref(), Some("../mmcv"));
+        assert!(mmcv.mirrors.is_empty());
+        assert_eq!(mmcv.tag.as_deref(), Some("v2.1.0"));
+    }
+
+    #[test]
+    fn adds_mirrors_to_the_manifest_repository() {
+        let mut manifest = Manifest::bundled().unwrap();
+        let mmcv = manifest
+            .packages
+            .iter_mut()
+            .find(|p| p.name == "mmcv")
+            .unwrap();
+        let repository = mmcv.repository.clone();
+
+        let source = SourceOverride {
+            mirrors: vec!["https://gitee.com/open-mmlab/mmcv.git".to_string()],
+            commit: Some("0123abcd".to_string()),
+            ..Default::default()
+        };
+        source.apply_to(mmcv).unwrap();
+        assert_eq!(mmcv.repository, repository);
+        assert_eq!(mmcv.mirrors, source.mirrors);
+        assert_eq!(mmcv.commit.as_deref(), Some("0123abcd"));
+        assert_eq!(mmcv.tag, None);
+    }
+
+    #[test]
+    fn rejects_ambiguous_or_empty_overrides() {
+        let mut manifest = Manifest::bundled().unwrap();
+        let mmcv = &mut manifest.packages[0];
+
+        let both = SourceOverride {
+            path: Some("../mmcv".to_string()),
+            tarball: Some("mmcv.tar.gz".to_string()),
+            ..Default::default()
+        };
+        assert!(both.apply_to(mmcv).is_err());
+        assert!(SourceOverride::default().apply_to(mmcv).is_err());
+    }
+}
@@ -0,0 +1,717 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+use crate::cache;
+use crate::compat::{Specifier, Version};
+use crate::patch::{PatchOutcome, PatchStatus};
+
+/// Per-package diffs the user keeps next to the project: `patches/<package>/*.patch`.
+pub const USER_PATCH_DIR: &str = "patches";
+const PATCH_EXTENSIONS: &[&str] = &["patch", "diff"];
+/// Context lines a hunk may drop at either end, like `patch --fuzz 2`.
+const MAX_FUZZ: usize = 2;
+
+/// A diff shipped with the installer from its `patches/<package>/`, applied
+/// to the versions of the package that `versions` matches.
+struct Bundled {
+    package: &'static str,
+    versions: &'static str,
+    file: &'static str,
+    content: &'static str,
+}
+
+const BUNDLED: &[Bundled] = &[Bundled {
+    package: "mmengine",
+    versions: ">=0.7.0,<1.0.0",
+    file: "0001-load-from-local-weights-only.diff",
+    content: include_str!("../patches/mmengine/0001-load-from-local-weights-only.diff"),
+}];
+
+/// A unified diff for one package, parsed up front so a malformed file fails
+/// before anything is cloned.
+#[derive(Debug, Clone)]
+pub struct DiffPatch {
+    pub name: String,
+    pub sha256: String,
+    files: Vec<FilePatch>,
+}
+
+#[derive(Debug, Clone)]
+struct FilePatch {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone)]
+struct Hunk {
+    header: String,
+    old_start: usize,
+    new_start: usize,
+    lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl DiffPatch {
+    pub fn parse(name: &str, content: &str) -> Result<Self> {
+        let files = parse_files(content).with_context(|| format!("invalid diff {name}"))?;
+        if files.is_empty() {
+            bail!("{name} contains no file changes");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            sha256: cache::hex(&Sha256::digest(content.as_bytes())),
+            files,
+        })
+    }
+
+    /// Paths the diff touches, relative to the source tree.
+    pub fn paths(&self) -> Vec<&str> {
+        self.files.iter().map(FilePatch::target).collect()
+    }
+
+    /// Applies every file of the diff, or none: a diff whose reverse applies
+    /// cleanly is reported as already applied.
+    pub fn apply(&self, source_dir: &Path) -> Result<PatchOutcome> {
+        let forward: Result<Vec<Change>> = self
+            .files
+            .iter()
+            .map(|file| file.patched(source_dir, false))
+            .collect();
+        let changes = match forward {
+            Ok(changes) => changes,
+            Err(error) => {
+                let reversed = self
+                    .files
+                    .iter()
+                    .all(|file| file.patched(source_dir, true).is_ok());
+                return Ok(if reversed {
+                    PatchOutcome::new(PatchStatus::AlreadyApplied, "")
+                } else {
+                    PatchOutcome::new(PatchStatus::Failed, format!("{error:#}"))
+                });
+            }
+        };
+
+        for change in &changes {
+            change.write()?;
+        }
+        Ok(PatchOutcome::new(
+            PatchStatus::Applied,
+            format!("{} file(s)", changes.len()),
+        ))
+    }
+}
+
+/// A file change computed in memory, so a diff never half-applies.
+enum Change {
+    Write(PathBuf, String),
+    Remove(PathBuf),
+}
+
+impl Change {
+    fn write(&self) -> Result<()> {
+        match self {
+            Change::Write(path, content) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("failed to create {}", parent.display()))?;
+                }
+                fs::write(path, content)
+                    .with_context(|| format!("failed writing {}", path.display()))
+            }
+            Change::Remove(path) => fs::remove_file(path)
+                .with_context(|| format!("failed to remove {}", path.display())),
+        }
+    }
+}
+
+/// The bundled diffs for this package version and the user's from
+/// `<patch_dir>/<package>/` (normally `patches/`), together in file name
+/// order. A user file named like a bundled diff replaces it, and an empty one
+/// turns it off.
+pub fn discover(package: &str, version: &str, patch_dir: &Path) -> Result<Vec<DiffPatch>> {
+    let version = Version::parse(version).ok();
+    let mut diffs: BTreeMap<String, (String, String)> = BTreeMap::new();
+    for bundled in BUNDLED.iter().filter(|bundled| bundled.package == package) {
+        let matches = Specifier::parse(bundled.versions)?;
+        if version
+            .as_ref()
+            .is_some_and(|version| matches.matches(version))
+        {
+            diffs.insert(
+                bundled.file.to_string(),
+                (
+                    format!("bundled {package}/{}", bundled.file),
+                    bundled.content.to_string(),
+                ),
+            );
+        }
+    }
+
+    let dir = patch_dir.join(package);
+    if dir.is_dir() {
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("failed to list {}", dir.display()))?;
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("failed to list {}", dir.display()))?
+                .path();
+            if !path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| PATCH_EXTENSIONS.contains(&ext))
+            {
+                continue;
+            }
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("failed reading {}", path.display()))?;
+            let file = cache::file_name(&path);
+            if content.trim().is_empty() {
+                diffs.remove(&file);
+            } else {
+                diffs.insert(file, (path.display().to_string(), content));
+            }
+        }
+    }
+
+    diffs
+        .into_values()
+        .map(|(name, content)| DiffPatch::parse(&name, &content))
+        .collect()
+}
+
+fn parse_files(content: &str) -> Result<Vec<FilePatch>> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut files = Vec::new();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let Some(old) = lines[index].strip_prefix("--- ") else {
+            // `diff --git`, `index` and commit message lines carry nothing we need.
+            index += 1;
+            continue;
+        };
+        let Some(new) = lines
+            .get(index + 1)
+            .and_then(|line| line.strip_prefix("+++ "))
+        else {
+            bail!("line {}: `--- ` is not followed by `+++ `", index + 1);
+        };
+        let old_path = parse_path(old).with_context(|| format!("line {}", index + 1))?;
+        let new_path = parse_path(new).with_context(|| format!("line {}", index + 2))?;
+        index += 2;
+
+        let mut hunks = Vec::new();
+        while let Some(header) = lines.get(index).filter(|line| line.starts_with("@@")) {
+            let ((old_start, mut old_left), (new_start, mut new_left)) = parse_hunk_header(header)
+                .with_context(|| format!("line {}: malformed hunk header", index + 1))?;
+            let mut hunk = Hunk {
+                header: header.to_string(),
+                old_start,
+                new_start,
+                lines: Vec::new(),
+            };
+            index += 1;
+
+            while old_left > 0 || new_left > 0 {
+                let Some(line) = lines.get(index) else {
+                    bail!("hunk `{header}` ends early");
+                };
+                index += 1;
+                // Some editors strip the single space of empty context lines.
+                let (marker, text) = line.split_at(line.len().min(1));
+                match marker {
+                    " " | "" => {
+                        hunk.lines.push(HunkLine::Context(text.to_string()));
+                        old_left = old_left.saturating_sub(1);
+                        new_left = new_left.saturating_sub(1);
+                    }
+                    "-" => {
+                        hunk.lines.push(HunkLine::Remove(text.to_string()));
+                        old_left = old_left.saturating_sub(1);
+                    }
+                    "+" => {
+                        hunk.lines.push(HunkLine::Add(text.to_string()));
+                        new_left = new_left.saturating_sub(1);
+                    }
+                    "\\" => {}
+                    _ => bail!("line {index}: unexpected `{line}` inside hunk `{header}`"),
+                }
+            }
+            if lines.get(index).is_some_and(|line| line.starts_with('\\')) {
+                index += 1;
+            }
+            hunks.push(hunk);
+        }
+
+        files.push(FilePatch {
+            old_path,
+            new_path,
+            hunks,
+        });
+    }
+    Ok(files)
+}
+
+/// `@@ -12,7 +12,8 @@ def f():` → ((12, 7), (12, 8)); a missing count means 1.
+fn parse_hunk_header(header: &str) -> Option<((usize, usize), (usize, usize))> {
+    let ranges = header.strip_prefix("@@ ")?.split(" @@").next()?;
+    let (old, new) = ranges.split_once(' ')?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    Some((
+        range(old.strip_prefix('-')?)?,
+        range(new.strip_prefix('+')?)?,
+    ))
+}
+
+/// Drops timestamps and git's `a/`/`b/` prefixes; `/dev/null` means none.
+/// Like manifest patch paths, the rest must stay inside the source tree.
+fn parse_path(raw: &str) -> Result<Option<String>> {
+    let path = raw.split('\t').next().unwrap_or(raw).trim();
+    if path == "/dev/null" {
+        return Ok(None);
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    let relative = Path::new(path);
+    if path.is_empty()
+        || relative.is_absolute()
+        || relative.components().any(|c| c.as_os_str() == "..")
+    {
+        bail!("diff path must be relative to the source tree: {path}");
+    }
+    Ok(Some(path.to_string()))
+}
+
+impl FilePatch {
+    fn target(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The file's content after the hunks (or before them, with `reverse`).
+    fn patched(&self, source_dir: &Path, reverse: bool) -> Result<Change> {
+        let path = source_dir.join(self.target());
+        let (from, to) = if reverse {
+            (&self.new_path, &self.old_path)
+        } else {
+            (&self.old_path, &self.new_path)
+        };
+
+        if to.is_none() {
+            if !path.is_file() {
+                bail!("{} does not exist", self.target());
+            }
+            // Like any other change, a deletion only applies to the content
+            // it was written against.
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("failed reading {}", path.display()))?;
+            let expected: Vec<&str> = self
+                .hunks
+                .iter()
+                .flat_map(|hunk| hunk.sides(0, reverse).0)
+                .collect();
+            if !content.lines().eq(expected.iter().copied()) {
+                bail!(
+                    "{} differs from the file the diff deletes; refusing to delete it",
+                    self.target()
+                );
+            }
+            return Ok(Change::Remove(path));
+        }
+
+        let (mut lines, trailing_newline) = if from.is_some() {
+            if !path.is_file() {
+                bail!("{} does not exist", self.target());
+            }
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("failed reading {}", path.display()))?;
+            let lines = content.lines().map(ToString::to_string).collect();
+            (lines, content.is_empty() || content.ends_with('\n'))
+        } else {
+            if path.exists() {
+                bail!("{} already exists", self.target());
+            }
+            (Vec::new(), true)
+        };
+
+        let mut offset = 0isize;
+        for (number, hunk) in self.hunks.iter().enumerate() {
+            let Some(next_offset) = hunk.apply(&mut lines, offset, reverse) else {
+                bail!(
+                    "hunk #{} of {} does not match near line {} (tried any offset and fuzz up to {MAX_FUZZ}):\n{}",
+                    number + 1,
+                    self.target(),
+                    hunk.old_start.max(1),
+                    hunk.render()
+                );
+            };
+            offset = next_offset;
+        }
+
+        let mut content = lines.join("\n");
+        if trailing_newline && !content.is_empty() {
+            content.push('\n');
+        }
+        Ok(Change::Write(path, content))
+    }
+}
+
+impl Hunk {
+    /// Old and new text with up to `fuzz` context lines dropped at each end,
+    /// plus how many were dropped at the start. At least one context line
+    /// stays on each end that has any, so fuzz never leaves a hunk with
+    /// nothing to anchor it.
+    fn sides(&self, fuzz: usize, reverse: bool) -> (Vec<&str>, Vec<&str>, usize) {
+        let is_context = |line: &&HunkLine| matches!(line, HunkLine::Context(_));
+        let fuzzed = |context: usize| context.saturating_sub(1).min(fuzz);
+        let leading = fuzzed(self.lines.iter().take_while(is_context).count());
+        let trailing = fuzzed(self.lines.iter().rev().take_while(is_context).count());
+        let end = self.lines.len().saturating_sub(trailing).max(leading);
+
+        let mut old = Vec::new();
+        let mut new = Vec::new();
+        for line in &self.lines[leading..end] {
+            match line {
+                HunkLine::Context(text) => {
+                    old.push(text.as_str());
+                    new.push(text.as_str());
+                }
+                HunkLine::Remove(text) => old.push(text.as_str()),
+                HunkLine::Add(text) => new.push(text.as_str()),
+            }
+        }
+        if reverse {
+            (new, old, leading)
+        } else {
+            (old, new, leading)
+        }
+    }
+
+    /// Applies the hunk at the match closest to its recorded position shifted
+    /// by `offset` and returns the offset for the next hunk.
+    fn apply(&self, lines: &mut Vec<String>, offset: isize, reverse: bool) -> Option<isize> {
+        let start = if reverse {
+            self.new_start
+        } else {
+            self.old_start
+        };
+        let recorded = start.saturating_sub(1) as isize + offset;
+        for fuzz in 0..=MAX_FUZZ {
+            let (old, new, skipped) = self.sides(fuzz, reverse);
+            let expected = (recorded + skipped as isize).clamp(0, lines.len() as isize) as usize;
+            let position = if old.is_empty() {
+                // Nothing to match: only a hunk that starts the file (or
+                // creates it) has a place to go; anywhere else it would land
+                // blindly, and again on every run.
+                if start != 0 {
+                    return None;
+                }
+                0
+            } else {
+                let Some(position) = find_block(lines, &old, expected) else {
+                    continue;
+                };
+                position
+            };
+            lines.splice(
+                position..position + old.len(),
+                new.iter().map(ToString::to_string),
+            );
+            return Some(
+                offset
+                    + (position as isize - expected as isize)
+                    + (new.len() as isize - old.len() as isize),
+            );
+        }
+        None
+    }
+
+    fn render(&self) -> String {
+        let mut rendered = self.header.clone();
+        for line in &self.lines {
+            let (marker, text) = match line {
+                HunkLine::Context(text) => (' ', text),
+                HunkLine::Remove(text) => ('-', text),
+                HunkLine::Add(text) => ('+', text),
+            };
+            rendered.push('\n');
+            rendered.push(marker);
+            rendered.push_str(text);
+        }
+        rendered
+    }
+}
+
+/// The match for `block` closest to `expected`, searching outwards.
+fn find_block(lines: &[String], block: &[&str], expected: usize) -> Option<usize> {
+    let matches_at = |start: usize| {
+        start + block.len() <= lines.len()
+            && lines[start..start + block.len()]
+                .iter()
+                .zip(block)
+                .all(|(line, wanted)| line.trim_end_matches('\r') == *wanted)
+    };
+    (0..=lines.len()).find_map(|distance| {
+        [
+            expected.checked_add(distance),
+            expected.checked_sub(distance),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|&start| matches_at(start))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch source tree holding `file.py`, removed when dropped.
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str, content: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "mmaction-install-diff-{name}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("file.py"), content).unwrap();
+            Self(dir)
+        }
+
+        fn apply(&self, diff: &str) -> PatchOutcome {
+            DiffPatch::parse("test.patch", diff)
+                .unwrap()
+                .apply(&self.0)
+                .unwrap()
+        }
+
+        fn content(&self) -> String {
+            fs::read_to_string(self.0.join("file.py")).unwrap()
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn numbered(range: std::ops::RangeInclusive<usize>) -> String {
+        range.map(|n| format!("l{n}\n")).collect()
+    }
+
+    const INSERT_AFTER_L3: &str = "\
+--- a/file.py
++++ b/file.py
+@@ -2,4 +2,5 @@
+ l2
+ l3
++new
+ l4
+ l5
+";
+
+    #[test]
+    fn applies_at_the_recorded_position() {
+        let tree = Tree::new("recorded", &numbered(1..=8));
+        assert_eq!(tree.apply(INSERT_AFTER_L3).status, PatchStatus::Applied);
+        assert!(tree.content().starts_with("l1\nl2\nl3\nnew\nl4\n"));
+    }
+
+    #[test]
+    fn applies_at_an_offset() {
+        let tree = Tree::new("offset", &format!("x\ny\nz\n{}", numbered(1..=8)));
+        assert_eq!(tree.apply(INSERT_AFTER_L3).status, PatchStatus::Applied);
+        assert!(tree.content().contains("l3\nnew\nl4\n"));
+    }
+
+    #[test]
+    fn fuzz_ignores_outer_context_lines() {
+        let tree = Tree::new("fuzz", "l1\nchanged\nl3\nl4\nl5\nl6\n");
+        assert_eq!(tree.apply(INSERT_AFTER_L3).status, PatchStatus::Applied);
+        assert_eq!(tree.content(), "l1\nchanged\nl3\nnew\nl4\nl5\nl6\n");
+    }
+
+    #[test]
+    fn reports_an_applied_diff_as_already_applied() {
+        let tree = Tree::new("already", &numbered(1..=8));
+        tree.apply(INSERT_AFTER_L3);
+        let patched = tree.content();
+        assert_eq!(
+            tree.apply(INSERT_AFTER_L3).status,
+            PatchStatus::AlreadyApplied
+        );
+        assert_eq!(tree.content(), patched);
+    }
+
+    #[test]
+    fn shows_the_hunk_that_does_not_match() {
+        let tree = Tree::new("failing", "a\nb\nc\n");
+        let outcome = tree.apply("--- a/file.py\n+++ b/file.py\n@@ -1,2 +1,2 @@\n a\n-x\n+y\n");
+        assert_eq!(outcome.status, PatchStatus::Failed);
+        assert!(outcome.detail.contains("hunk #1 of file.py"));
+        assert!(outcome.detail.contains("-x\n+y"));
+        assert_eq!(tree.content(), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn fuzz_keeps_one_context_line_on_each_side() {
+        // None of the context exists; fuzz must not strip it all and insert
+        // the line blindly at the recorded position.
+        let tree = Tree::new("anchor", &numbered(1..=4));
+        let diff = "--- a/file.py\n+++ b/file.py\n@@ -5,4 +5,5 @@\n l5\n l6\n+new\n l7\n l8\n";
+        assert_eq!(tree.apply(diff).status, PatchStatus::Failed);
+        assert_eq!(tree.content(), numbered(1..=4));
+    }
+
+    #[test]
+    fn context_free_hunks_only_apply_at_the_start_of_a_file() {
+        let tree = Tree::new("zero-context", &numbered(1..=4));
+        let middle = "--- a/file.py\n+++ b/file.py\n@@ -2,0 +3 @@\n+new\n";
+        assert_eq!(tree.apply(middle).status, PatchStatus::Failed);
+
+        let start = "--- a/file.py\n+++ b/file.py\n@@ -0,0 +1 @@\n+new\n";
+        assert_eq!(tree.apply(start).status, PatchStatus::Applied);
+        assert_eq!(tree.content(), format!("new\n{}", numbered(1..=4)));
+    }
+
+    #[test]
+    fn creates_and_deletes_files() {
+        let tree = Tree::new("create", "a\n");
+        let create = "--- /dev/null\n+++ b/pkg/new.py\n@@ -0,0 +1,2 @@\n+x = 1\n+y = 2\n";
+        assert_eq!(tree.apply(create).status, PatchStatus::Applied);
+        assert_eq!(
+            fs::read_to_string(tree.0.join("pkg/new.py")).unwrap(),
+            "x = 1\ny = 2\n"
+        );
+        assert_eq!(tree.apply(create).status, PatchStatus::AlreadyApplied);
+
+        let delete = "--- a/file.py\n+++ /dev/null\n@@ -1 +0,0 @@\n-a\n";
+        assert_eq!(tree.apply(delete).status, PatchStatus::Applied);
+        assert!(!tree.0.join("file.py").exists());
+    }
+
+    #[test]
+    fn deletes_only_the_content_the_diff_was_written_against() {
+        let tree = Tree::new("delete-changed", "a\nlocal edit\n");
+        let delete = "--- a/file.py\n+++ /dev/null\n@@ -1 +0,0 @@\n-a\n";
+        let outcome = tree.apply(delete);
+        assert_eq!(outcome.status, PatchStatus::Failed);
+        assert!(outcome.detail.contains("refusing to delete"));
+        assert_eq!(tree.content(), "a\nlocal edit\n");
+    }
+
+    #[test]
+    fn selects_bundled_diffs_by_version() {
+        let none = Path::new("no-patches");
+        for bundled in BUNDLED {
+            let patch = DiffPatch::parse(bundled.file, bundled.content).unwrap();
+            assert!(!patch.paths().is_empty(), "{}", bundled.file);
+        }
+        assert_eq!(discover("mmengine", "0.10.7", none).unwrap().len(), 1);
+        assert!(discover("mmengine", "0.6.0", none).unwrap().is_empty());
+        assert!(
+            discover("mmengine", "v0.10.7-3-gabc", none)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(discover("mmcv", "2.1.0", none).unwrap().is_empty());
+    }
+
+    #[test]
+    fn applies_the_bundled_mmengine_diff() {
+        let tree = Tree::new("bundled", "");
+        let checkpoint = tree.0.join("mmengine/runner/checkpoint.py");
+        fs::create_dir_all(checkpoint.parent().unwrap()).unwrap();
+        fs::write(
+            &checkpoint,
+            "def load_from_local(filename, map_location):\n    \"\"\"Load.\"\"\"\n    filename = osp.expanduser(filename)\n    if not osp.isfile(filename):\n        raise FileNotFoundError(f'{filename} can not be found.')\n    checkpoint = torch.load(filename, map_location=map_location)\n    return checkpoint\n",
+        )
+        .unwrap();
+
+        let patch = discover("mmengine", "0.10.7", Path::new("no-patches")).unwrap();
+        assert_eq!(
+            patch[0].apply(&tree.0).unwrap().status,
+            PatchStatus::Applied
+        );
+        assert!(
+            fs::read_to_string(&checkpoint)
+                .unwrap()
+                .contains("map_location=map_location, weights_only=False)")
+        );
+        assert_eq!(
+            patch[0].apply(&tree.0).unwrap().status,
+            PatchStatus::AlreadyApplied
+        );
+    }
+
+    #[test]
+    fn user_diffs_replace_or_turn_off_bundled_ones() {
+        let tree = Tree::new("override", "");
+        let dir = tree.0.join("mmengine");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(BUNDLED[0].file);
+
+        fs::write(&file, INSERT_AFTER_L3).unwrap();
+        fs::write(dir.join("0002-local.patch"), INSERT_AFTER_L3).unwrap();
+        let names: Vec<String> = discover("mmengine", "0.10.7", &tree.0)
+            .unwrap()
+            .into_iter()
+            .map(|patch| patch.name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                file.display().to_string(),
+                dir.join("0002-local.patch").display().to_string()
+            ]
+        );
+
+        fs::write(&file, "\n").unwrap();
+        let patches = discover("mmengine", "0.10.7", &tree.0).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert!(patches[0].name.ends_with("0002-local.patch"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_source_tree() {
+        for path in ["/etc/passwd", "../outside.py", "a/pkg/../../x.py"] {
+            let diff = format!("--- {path}\n+++ {path}\n@@ -1 +1 @@\n-a\n+b\n");
+            assert!(DiffPatch::parse("test.patch", &diff).is_err(), "{path}");
+        }
+    }
+
+    #[test]
+    fn parses_hunk_headers() {
+        assert_eq!(
+            parse_hunk_header("@@ -12,7 +12,8 @@ def f():"),
+            Some(((12, 7), (12, 8)))
+        );
+        assert_eq!(parse_hunk_header("@@ -3 +3,0 @@"), Some(((3, 1), (3, 0))));
+        assert_eq!(parse_hunk_header("@@ garbage @@"), None);
+    }
+}
@@ -0,0 +1,371 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Result, bail};
+use console::Style;
+
+use crate::pipeline::Pipeline;
+use crate::{App, print_table, query_python, uv_is_available, venv_python_version};
+
+// Torch 2.x extensions are compiled as C++17, which needs GCC 9 or newer.
+const MIN_GCC_MAJOR: u32 = 9;
+const WARN_FREE_GIB: u64 = 10;
+const FAIL_FREE_GIB: u64 = 2;
+pub const CXX_COMPILERS: &[&str] = &["c++", "g++", "clang++"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+pub struct CheckResult {
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+    hint: &'static str,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: "",
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint,
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint,
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status == CheckStatus::Fail
+    }
+
+    fn summary(&self) -> String {
+        if self.hint.is_empty() {
+            format!("{}: {}", self.name, self.detail)
+        } else {
+            format!("{}: {} (hint: {})", self.name, self.detail, self.hint)
+        }
+    }
+}
+
+/// Runs the environment checks. `include_tools` adds the git/uv checks that
+/// only matter to `doctor`; the preflight runs after uv is already ensured.
+pub fn run_checks(app: &App, include_tools: bool) -> Result<Vec<CheckResult>> {
+    let mut checks = Vec::new();
+
+    if include_tools {
+        checks.push(check_git());
+        checks.push(check_uv());
+    }
+    checks.push(check_compiler("C compiler", "CC", &["cc", "gcc", "clang"]));
+    checks.push(check_compiler("C++ compiler", "CXX", CXX_COMPILERS));
+    checks.push(check_ninja(app));
+    checks.push(check_disk_space());
+    checks.push(check_python(app)?);
+    checks.push(check_python_headers(app)?);
+    checks.push(check_torch(app)?);
+
+    Ok(checks)
+}
+
+pub fn print_report(checks: &[CheckResult]) {
+    let rows: Vec<[String; 4]> = checks
+        .iter()
+        .map(|check| {
+            let status = match check.status {
+                CheckStatus::Pass => "ok",
+                CheckStatus::Warn => "warn",
+                CheckStatus::Fail => "FAIL",
+            };
+            [
+                check.name.to_string(),
+                status.to_string(),
+                check.detail.clone(),
+                check.hint.to_string(),
+            ]
+        })
+        .collect();
+    let styles: Vec<Style> = checks
+        .iter()
+        .map(|check| match check.status {
+            CheckStatus::Pass => Style::new(),
+            CheckStatus::Warn => Style::new().yellow(),
+            CheckStatus::Fail => Style::new().red(),
+        })
+        .collect();
+
+    print_table(["Check", "Status", "Details", "Hint"], &rows, &styles);
+}
+
+/// Build checks run before any package is compiled. Skipped when every
+/// package already has a cached wheel.
+pub fn preflight(app: &App, warnings: &mut Vec<String>) -> Result<()> {
+    if app.skip_preflight {
+        warnings.push("preflight checks skipped (--skip-preflight)".to_string());
+        return Ok(());
+    }
+
+    let mut to_build = Vec::new();
+    for package in &app.packages {
+        if Pipeline::new(app, package).needs_build()? {
+            to_build.push(package.name.as_str());
+        }
+    }
+    if to_build.is_empty() {
+        app.plan("every package has a cached wheel; skipping build preflight");
+        return Ok(());
+    }
+
+    let checks = run_checks(app, false)?;
+    warnings.extend(
+        checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Warn)
+            .map(CheckResult::summary),
+    );
+
+    let failures: Vec<String> = checks
+        .iter()
+        .filter(|check| check.failed())
+        .map(|check| format!("  - {}", check.summary()))
+        .collect();
+    if failures.is_empty() {
+        return Ok(());
+    }
+    if app.dry_run {
+        warnings.extend(failures.iter().map(|line| {
+            format!(
+                "the build would fail preflight: {}",
+                line.trim_start_matches("  - ")
+            )
+        }));
+        return Ok(());
+    }
+
+    bail!(
+        "the build environment is not ready to build {} (run `setup doctor` for the full report, or pass --skip-preflight):\n{}",
+        to_build.join(", "),
+        failures.join("\n")
+    )
+}
+
+fn check_git() -> CheckResult {
+    match tool_version("git") {
+        Some(version) => CheckResult::pass("git", version),
+        None => CheckResult::fail(
+            "git",
+            "not found on PATH",
+            "install git (e.g. `apt install git`)",
+        ),
+    }
+}
+
+fn check_uv() -> CheckResult {
+    if !uv_is_available() {
+        return CheckResult::warn(
+            "uv",
+            "not found on PATH",
+            "`setup install` installs it automatically; or add ~/.local/bin to PATH",
+        );
+    }
+    CheckResult::pass(
+        "uv",
+        tool_version("uv").unwrap_or_else(|| "available".to_string()),
+    )
+}
+
+fn check_compiler(name: &'static str, env_var: &str, candidates: &[&str]) -> CheckResult {
+    let hint = "install a compiler toolchain (e.g. `apt install build-essential` or `xcode-select --install`)";
+
+    let configured = std::env::var(env_var)
+        .ok()
+        .filter(|value| !value.is_empty());
+    let Some((program, version)) = find_compiler(env_var, candidates) else {
+        let detail = match configured {
+            Some(program) => format!("${env_var}={program} is not runnable"),
+            None => format!("none of {} found", candidates.join(", ")),
+        };
+        return CheckResult::fail(name, detail, hint);
+    };
+
+    let detail = format!("{program}: {version}");
+    let is_gcc = !version.contains("clang");
+    match gcc_major(&version) {
+        Some(major) if is_gcc && major < MIN_GCC_MAJOR => CheckResult::warn(
+            name,
+            detail,
+            "torch 2.x extensions need GCC 9+; set CC/CXX to a newer compiler",
+        ),
+        _ => CheckResult::pass(name, detail),
+    }
+}
+
+/// The compiler named by `env_var` (e.g. `CXX`), or else the first runnable
+/// candidate, with its version line.
+pub fn find_compiler(env_var: &str, candidates: &[&str]) -> Option<(String, String)> {
+    let programs: Vec<String> = match std::env::var(env_var) {
+        Ok(program) if !program.is_empty() => vec![program],
+        _ => candidates.iter().map(ToString::to_string).collect(),
+    };
+    programs
+        .into_iter()
+        .find_map(|program| tool_version(&program).map(|version| (program, version)))
+}
+
+fn gcc_major(version_line: &str) -> Option<u32> {
+    version_line
+        .split_whitespace()
+        .filter(|token| token.contains('.'))
+        .find_map(|token| token.split('.').next()?.parse::<u32>().ok())
+}
+
+fn check_ninja(app: &App) -> CheckResult {
+    let venv_ninja = app.venv_dir.join("bin/ninja");
+    let version = tool_version(&venv_ninja).or_else(|| tool_version("ninja"));
+    match version {
+        Some(version) => CheckResult::pass("ninja", version),
+        None => CheckResult::warn(
+            "ninja",
+            "not found; mmcv falls back to a much slower serial build",
+            "`uv pip install --python <venv>/bin/python ninja` or `apt install ninja-build`",
+        ),
+    }
+}
+
+fn check_disk_space() -> CheckResult {
+    let name = "disk space";
+    let Some(free_kib) = free_disk_kib(Path::new(".")) else {
+        return CheckResult::warn(name, "could not determine free space", "check `df -h .`");
+    };
+
+    let free_gib = free_kib / (1024 * 1024);
+    let detail = format!("{free_gib} GiB free in the current directory");
+    if free_gib < FAIL_FREE_GIB {
+        CheckResult::fail(
+            name,
+            detail,
+            "free up space; clones and wheel builds need several GiB",
+        )
+    } else if free_gib < WARN_FREE_GIB {
+        CheckResult::warn(
+            name,
+            detail,
+            "mmcv builds can use several GiB of temporary space",
+        )
+    } else {
+        CheckResult::pass(name, detail)
+    }
+}
+
+fn free_disk_kib(path: &Path) -> Option<u64> {
+    let output = Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()
+}
+
+fn check_python(app: &App) -> Result<CheckResult> {
+    let name = "venv Python";
+    if !app.python_bin().exists() {
+        return Ok(CheckResult::warn(
+            name,
+            format!("no venv at {}", app.venv_dir.display()),
+            "`setup install` creates it",
+        ));
+    }
+    Ok(match venv_python_version(app)? {
+        Some(version) => CheckResult::pass(name, version),
+        None => CheckResult::fail(
+            name,
+            format!("{} does not run", app.python_bin().display()),
+            "delete the venv and rerun `setup install`",
+        ),
+    })
+}
+
+fn check_python_headers(app: &App) -> Result<CheckResult> {
+    let name = "Python headers";
+    if !app.python_bin().exists() {
+        return Ok(CheckResult::warn(
+            name,
+            "no venv yet",
+            "`setup install` creates it",
+        ));
+    }
+
+    let include = query_python(
+        app,
+        "import os, sysconfig; p = os.path.join(sysconfig.get_paths()['include'], 'Python.h'); print(p if os.path.exists(p) else '')",
+    )?;
+    Ok(match include {
+        Some(path) => CheckResult::pass(name, path),
+        None => CheckResult::fail(
+            name,
+            "Python.h not found",
+            "install the python3-dev package for this Python, or use a uv-managed Python",
+        ),
+    })
+}
+
+fn check_torch(app: &App) -> Result<CheckResult> {
+    let name = "torch";
+    let hint = "add torch to the project dependencies; `setup install` installs it before building";
+    if !app.python_bin().exists() {
+        return Ok(CheckResult::warn(
+            name,
+            "no venv yet",
+            "`setup install` creates it and installs torch",
+        ));
+    }
+
+    let script = "import torch; c = torch.version.cuda; print(torch.__version__ + (' (CUDA ' + c + ')' if c else ' (CPU)'))";
+    Ok(match query_python(app, script)? {
+        Some(version) => CheckResult::pass(name, version),
+        None => CheckResult::fail(name, "cannot be imported in the venv", hint),
+    })
+}
+
+pub fn tool_version(program: impl AsRef<Path>) -> Option<String> {
+    let output = Command::new(program.as_ref())
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().next().map(|line| line.trim().to_string())
+}
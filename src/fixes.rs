@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::cache;
+use crate::compat::{Specifier, Version};
+use crate::patch::{self, PatchOutcome, PatchStatus};
+
+pub const USER_CATALOG_FILE: &str = "mmaction-install-fixes.toml";
+const BUNDLED_CATALOG: &str = include_str!("../manifests/fixes.toml");
+
+/// Known compatibility fixes, keyed by package and version range.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    pub fixes: Vec<Fix>,
+}
+
+/// One fix for one package.
+#[derive(Debug, Clone)]
+pub struct Fix {
+    pub id: String,
+    pub package: String,
+    pub versions: String,
+    pub reason: String,
+    pub paths: Vec<String>,
+    /// Literal `[find, replacement]` pairs, applied in order.
+    pub replace: Vec<(String, String)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CatalogFile {
+    #[serde(rename = "fix", default)]
+    fixes: Vec<FixEntry>,
+}
+
+/// A `[[fix]]` table: either one package with `package`, `versions` and
+/// `paths`, or several with a `packages` list of them sharing `replace`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct FixEntry {
+    id: String,
+    package: Option<String>,
+    versions: Option<String>,
+    paths: Option<Vec<String>>,
+    #[serde(default)]
+    packages: Vec<FixTarget>,
+    reason: String,
+    replace: Vec<(String, String)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixTarget {
+    package: String,
+    versions: String,
+    paths: Vec<String>,
+}
+
+impl FixEntry {
+    fn into_fixes(self) -> Result<Vec<Fix>> {
+        let mut targets = self.packages;
+        match (self.package, self.versions, self.paths) {
+            (Some(package), Some(versions), Some(paths)) if targets.is_empty() => {
+                targets.push(FixTarget {
+                    package,
+                    versions,
+                    paths,
+                });
+            }
+            (None, None, None) if !targets.is_empty() => {}
+            _ => bail!(
+                "fix {} needs either package, versions and paths, or a packages list",
+                self.id
+            ),
+        }
+        Ok(targets
+            .into_iter()
+            .map(|target| Fix {
+                id: self.id.clone(),
+                package: target.package,
+                versions: target.versions,
+                reason: self.reason.clone(),
+                paths: target.paths,
+                replace: self.replace.clone(),
+            })
+            .collect())
+    }
+}
+
+impl Catalog {
+    /// The bundled catalog, extended (and overridden by package and id) by
+    /// `local`, normally `mmaction-install-fixes.toml` in the current directory.
+    pub fn load(local: &Path) -> Result<Self> {
+        let mut catalog = Self::parse(BUNDLED_CATALOG, "bundled fix catalog")?;
+
+        if local.is_file() {
+            let origin = local.display().to_string();
+            let content =
+                fs::read_to_string(local).with_context(|| format!("failed reading {origin}"))?;
+            for fix in Self::parse(&content, &origin)?.fixes {
+                catalog
+                    .fixes
+                    .retain(|known| known.package != fix.package || known.id != fix.id);
+                catalog.fixes.push(fix);
+            }
+        }
+        Ok(catalog)
+    }
+
+    fn parse(content: &str, origin: &str) -> Result<Self> {
+        let file: CatalogFile =
+            toml::from_str(content).with_context(|| format!("invalid fix catalog: {origin}"))?;
+        let mut catalog = Self::default();
+        for entry in file.fixes {
+            catalog.fixes.extend(
+                entry
+                    .into_fixes()
+                    .with_context(|| format!("invalid fix catalog: {origin}"))?,
+            );
+        }
+        catalog
+            .validate()
+            .with_context(|| format!("invalid fix catalog: {origin}"))?;
+        Ok(catalog)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for fix in &self.fixes {
+            if !seen.insert((fix.package.as_str(), fix.id.as_str())) {
+                bail!(
+                    "fix {} is listed more than once for {}",
+                    fix.id,
+                    fix.package
+                );
+            }
+            Specifier::parse(&fix.versions)
+                .with_context(|| format!("fix {} has invalid versions", fix.id))?;
+            if fix.paths.is_empty() || fix.replace.is_empty() {
+                bail!("fix {} needs at least one path and one replacement", fix.id);
+            }
+            for path in &fix.paths {
+                let path = Path::new(path);
+                if path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+                    bail!(
+                        "fix {} path must be relative to the source tree: {}",
+                        fix.id,
+                        path.display()
+                    );
+                }
+            }
+            if fix.replace.iter().any(|(find, _)| find.is_empty()) {
+                bail!("fix {} has an empty find string", fix.id);
+            }
+        }
+        Ok(())
+    }
+
+    /// The fixes for this package version, in catalog order. None for a
+    /// version that is not PEP 440 (a git describe string, say), which the
+    /// compatibility check warns about.
+    pub fn matching(&self, package: &str, version: &str) -> Vec<Fix> {
+        let Ok(version) = Version::parse(version) else {
+            return Vec::new();
+        };
+        self.fixes
+            .iter()
+            .filter(|fix| fix.package == package)
+            .filter(|fix| {
+                Specifier::parse(&fix.versions).is_ok_and(|range| range.matches(&version))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Fix {
+    /// sha256 of what the fix does, so editing it in the catalog invalidates
+    /// the wheels built with it.
+    pub fn sha256(&self) -> String {
+        let mut hasher = Sha256::new();
+        for path in &self.paths {
+            hasher.update(format!("path {path}\n"));
+        }
+        for (find, replacement) in &self.replace {
+            hasher.update(format!("{find:?} -> {replacement:?}\n"));
+        }
+        cache::hex(&hasher.finalize())
+    }
+
+    /// Applies the replacements to every file under the fix's paths.
+    pub fn apply(&self, source_dir: &Path) -> Result<PatchOutcome> {
+        let mut replaced = 0usize;
+        let mut changed_files = 0usize;
+        let mut found_path = false;
+
+        for path in &self.paths {
+            let path = source_dir.join(path);
+            if !path.exists() {
+                continue;
+            }
+            found_path = true;
+            for file in patch::python_files(&path)? {
+                let content = fs::read_to_string(&file)
+                    .with_context(|| format!("failed reading {}", file.display()))?;
+                let mut rewritten = content.clone();
+                let mut count = 0;
+                for (find, replacement) in &self.replace {
+                    let (next, replacements) = replace_names(&rewritten, find, replacement);
+                    rewritten = next;
+                    count += replacements;
+                }
+                if count > 0 {
+                    fs::write(&file, rewritten)
+                        .with_context(|| format!("failed writing {}", file.display()))?;
+                    replaced += count;
+                    changed_files += 1;
+                }
+            }
+        }
+
+        Ok(if !found_path {
+            PatchOutcome::new(
+                PatchStatus::NotNeeded,
+                format!("no {}", self.paths.join(", ")),
+            )
+        } else if replaced > 0 {
+            PatchOutcome::new(
+                PatchStatus::Applied,
+                format!("{replaced} replacement(s) in {changed_files} file(s)"),
+            )
+        } else {
+            // The fix runs on a pristine tree, so finding nothing means the
+            // code never needed it (or upstream fixed it), not that it was
+            // applied before.
+            PatchOutcome::new(PatchStatus::NotNeeded, "none of the find strings occur")
+        })
+    }
+}
+
+/// Replaces `find` in `content`. Where `find` starts or ends with a name
+/// character, the match must not continue a longer name (or attribute) on
+/// that side.
+fn replace_names(content: &str, find: &str, replacement: &str) -> (String, usize) {
+    let bytes = content.as_bytes();
+    let check_start = find.as_bytes().first().is_some_and(|&b| is_name(b));
+    let check_end = find.as_bytes().last().is_some_and(|&b| is_name(b));
+
+    let mut result = String::with_capacity(content.len());
+    let mut copied = 0;
+    let mut count = 0;
+    for (start, _) in content.match_indices(find) {
+        let end = start + find.len();
+        if check_start && start > 0 && (is_name(bytes[start - 1]) || bytes[start - 1] == b'.') {
+            continue;
+        }
+        if check_end && bytes.get(end).is_some_and(|&b| is_name(b)) {
+            continue;
+        }
+        result.push_str(&content[copied..start]);
+        result.push_str(replacement);
+        copied = end;
+        count += 1;
+    }
+    result.push_str(&content[copied..]);
+    (result, count)
+}
+
+fn is_name(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_whole_names_only() {
+        let source = "a = np.Inf\nb = np.Infinity\nc = -np.Inf_x\nd = mynp.Inf\ne = x.np.Inf\n";
+        let (rewritten, count) = replace_names(source, "np.Inf", "np.inf");
+        assert_eq!(count, 1);
+        assert_eq!(
+            rewritten,
+            "a = np.inf\nb = np.Infinity\nc = -np.Inf_x\nd = mynp.Inf\ne = x.np.Inf\n"
+        );
+    }
+
+    #[test]
+    fn matches_punctuation_ends_anywhere() {
+        // `np.product(` ends with a parenthesis, so the name check only
+        // applies at its start.
+        let (rewritten, count) = replace_names(
+            "np.product(a) + np.product (b) + xnp.product(c)",
+            "np.product(",
+            "np.prod(",
+        );
+        assert_eq!(count, 1);
+        assert_eq!(rewritten, "np.prod(a) + np.product (b) + xnp.product(c)");
+    }
+
+    #[test]
+    fn keeps_the_precedence_of_negated_replacements() {
+        let catalog = Catalog::parse(BUNDLED_CATALOG, "bundled fix catalog").unwrap();
+        let fix = catalog
+            .matching("mmcv", "2.1.0")
+            .into_iter()
+            .find(|fix| fix.id == "numpy2-removed-aliases")
+            .unwrap();
+        let (find, replacement) = fix
+            .replace
+            .iter()
+            .find(|(find, _)| find == "np.NINF")
+            .unwrap();
+        let (rewritten, _) = replace_names("y = np.NINF ** 2", find, replacement);
+        assert_eq!(rewritten, "y = (-np.inf) ** 2");
+    }
+
+    #[test]
+    fn matches_fixes_by_package_and_version_range() {
+        let catalog = Catalog::parse(BUNDLED_CATALOG, "bundled fix catalog").unwrap();
+        let ids = |package, version| -> Vec<String> {
+            catalog
+                .matching(package, version)
+                .into_iter()
+                .map(|fix| fix.id)
+                .collect()
+        };
+        assert!(ids("mmcv", "2.1.0").contains(&"setup-without-pkg-resources".to_string()));
+        assert!(ids("mmcv", "1.7.2").contains(&"torch2-without-torch-six".to_string()));
+        assert!(!ids("mmcv", "1.7.2").contains(&"numpy2-removed-aliases".to_string()));
+        assert!(ids("mmpose", "1.3.2").is_empty());
+        assert!(ids("mmcv", "2.1.0-12-g0123abc").is_empty());
+    }
+
+    #[test]
+    fn expands_a_packages_list_into_one_fix_per_package() {
+        let catalog = Catalog::parse(BUNDLED_CATALOG, "bundled fix catalog").unwrap();
+        let numpy: Vec<&Fix> = catalog
+            .fixes
+            .iter()
+            .filter(|fix| fix.id == "numpy2-removed-aliases")
+            .collect();
+        let packages: Vec<&str> = numpy.iter().map(|fix| fix.package.as_str()).collect();
+        assert_eq!(packages, ["mmcv", "mmengine", "mmaction2"]);
+        assert_eq!(numpy[2].paths, ["mmaction"]);
+        assert_eq!(numpy[2].versions, ">=1.0.0");
+        assert_eq!(numpy[0].replace, numpy[2].replace);
+    }
+
+    #[test]
+    fn rejects_a_fix_with_both_or_neither_target_forms() {
+        let both = r#"
+            [[fix]]
+            id = "both"
+            package = "mmcv"
+            versions = ">=2.0.0"
+            paths = ["mmcv"]
+            packages = [{ package = "mmengine", versions = ">=0.7.0", paths = ["mmengine"] }]
+            reason = "test"
+            replace = [["a", "b"]]
+        "#;
+        let neither = r#"
+            [[fix]]
+            id = "neither"
+            package = "mmcv"
+            reason = "test"
+            replace = [["a", "b"]]
+        "#;
+        for content in [both, neither] {
+            let error = Catalog::parse(content, "test").unwrap_err();
+            assert!(format!("{error:#}").contains("packages list"), "{error:#}");
+        }
+    }
+
+    #[test]
+    fn reports_a_fix_that_finds_nothing_as_not_needed() {
+        let dir = std::env::temp_dir().join(format!(
+            "mmaction-install-fixes-not-needed-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("mmcv")).unwrap();
+        fs::write(dir.join("mmcv/ops.py"), "x = np.nan\n").unwrap();
+
+        let catalog = Catalog::parse(BUNDLED_CATALOG, "bundled fix catalog").unwrap();
+        let fix = catalog
+            .matching("mmcv", "2.1.0")
+            .into_iter()
+            .find(|fix| fix.id == "numpy2-removed-aliases")
+            .unwrap();
+        assert_eq!(fix.apply(&dir).unwrap().status, PatchStatus::NotNeeded);
+
+        fs::write(dir.join("mmcv/ops.py"), "x = np.NaN\n").unwrap();
+        let outcome = fix.apply(&dir).unwrap();
+        assert_eq!(outcome.status, PatchStatus::Applied);
+        assert_eq!(
+            fs::read_to_string(dir.join("mmcv/ops.py")).unwrap(),
+            "x = np.nan\n"
+        );
+
+        fs::remove_dir_all(dir.join("mmcv")).unwrap();
+        assert_eq!(fix.apply(&dir).unwrap().status, PatchStatus::NotNeeded);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
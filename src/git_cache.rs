@@ -0,0 +1,254 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Result, bail};
+use sha2::{Digest, Sha256};
+
+use crate::manifest::GitRef;
+use crate::pipeline::PackageSpec;
+use crate::{App, OutputMode, cache, lock_exclusive, run_command, sources};
+
+const GIT_DIR: &str = "git";
+
+/// A bare mirror of one repository under `$XDG_CACHE_HOME/mmaction-install/git`,
+/// shared by every project and fetched incrementally. Builds check out local
+/// clones of it instead of cloning over the network.
+pub struct Mirror {
+    dir: PathBuf,
+}
+
+impl Mirror {
+    /// The mirror for a repository URL; its mirrors fetch into the same one.
+    pub fn for_repository(repository: &str) -> Result<Self> {
+        Ok(Self {
+            dir: sources::cache_root()?
+                .join(GIT_DIR)
+                .join(format!("{}.git", mirror_name(repository))),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Blocks other installs from updating the mirror until the file is
+    /// dropped.
+    pub fn lock(&self, app: &App) -> Result<File> {
+        let mut path = self.dir.as_os_str().to_owned();
+        path.push(".lock");
+        lock_exclusive(app, Path::new(&path), "the git cache")
+    }
+
+    /// The commit `git_ref` resolves to in the mirror, if the mirror has it.
+    pub fn resolve(&self, git_ref: &GitRef) -> Option<String> {
+        if !self.dir.is_dir() {
+            return None;
+        }
+        let revision = match git_ref {
+            GitRef::Tag(tag) => format!("refs/tags/{tag}^{{commit}}"),
+            GitRef::Branch(branch) => format!("refs/heads/{branch}^{{commit}}"),
+            GitRef::Commit(commit) => format!("{commit}^{{commit}}"),
+        };
+        let output = Command::new("git")
+            .arg("--git-dir")
+            .arg(&self.dir)
+            .args(["rev-parse", "--verify", "--quiet"])
+            .arg(revision)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Fetches the branches and tags from the first of `urls` that answers,
+    /// and a commit ref that none of them contains, and returns that URL.
+    pub fn update(&self, app: &App, spec: &PackageSpec, urls: &[String]) -> Result<String> {
+        if !self.dir.is_dir() {
+            let mut init = Command::new("git");
+            init.args(["init", "-q", "--bare"]).arg(&self.dir);
+            run_command(
+                app,
+                &format!("create {} git cache", spec.name),
+                init,
+                OutputMode::Quiet,
+            )?;
+        }
+
+        let mut failures = Vec::new();
+        for (index, url) in urls.iter().enumerate() {
+            let label = if index == 0 {
+                format!("fetch {}", spec.name)
+            } else {
+                format!("fetch {} from mirror {url}", spec.name)
+            };
+            match self.fetch_from(app, spec, url, &label) {
+                Ok(()) => {
+                    if app.dry_run && urls.len() > index + 1 {
+                        app.plan(&format!(
+                            "would fall back to {} if the fetch fails",
+                            urls[index + 1..].join(", ")
+                        ));
+                    }
+                    return Ok(url.clone());
+                }
+                Err(error) => failures.push(format!("  - {url}: {error:#}")),
+            }
+        }
+        bail!(
+            "could not fetch {} {} from any source:\n{}",
+            spec.name,
+            spec.git_ref,
+            failures.join("\n")
+        )
+    }
+
+    fn fetch_from(&self, app: &App, spec: &PackageSpec, url: &str, label: &str) -> Result<()> {
+        let mut fetch = self.git();
+        fetch
+            .args(["fetch", "-q", "--prune", "--force"])
+            .arg(url)
+            .args(["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"]);
+        run_command(app, label, fetch, OutputMode::Quiet)?;
+
+        // A commit outside every branch and tag (a pull request, say) is
+        // fetched by its SHA and kept under refs/pinned so gc leaves it.
+        if let GitRef::Commit(commit) = &spec.git_ref
+            && (app.dry_run || self.resolve(&spec.git_ref).is_none())
+        {
+            let mut fetch = self.git();
+            fetch
+                .args(["fetch", "-q"])
+                .arg(url)
+                .arg(format!("{commit}:refs/pinned/{commit}"));
+            run_command(app, label, fetch, OutputMode::Quiet)?;
+        }
+        Ok(())
+    }
+
+    /// A local clone of `commit` into `dir`, `.git` included. Objects are
+    /// hard-linked from the mirror where the filesystem allows it.
+    pub fn checkout(&self, app: &App, spec: &PackageSpec, commit: &str, dir: &Path) -> Result<()> {
+        let label = format!("check out {}", spec.name);
+        let mut clone = Command::new("git");
+        clone
+            .args(["clone", "-q", "--no-checkout"])
+            .arg(&self.dir)
+            .arg(dir);
+        run_command(app, &label, clone, OutputMode::Quiet)?;
+        let mut checkout = Command::new("git");
+        checkout
+            .arg("-C")
+            .arg(dir)
+            .args(["checkout", "-q", "--detach"])
+            .arg(commit);
+        run_command(app, &label, checkout, OutputMode::Quiet)
+    }
+
+    fn git(&self) -> Command {
+        let mut git = Command::new("git");
+        git.arg("--git-dir").arg(&self.dir);
+        git
+    }
+}
+
+/// `https://github.com/open-mmlab/mmcv.git` →
+/// `github.com_open-mmlab_mmcv-<hash>`. Several characters map to `_`, so
+/// the first 12 hex digits of the sha256 of the URL (without scheme and
+/// `.git`) keep repositories such as `a_b/c` and `a/b_c` apart.
+fn mirror_name(repository: &str) -> String {
+    let trimmed = repository
+        .split_once("://")
+        .map_or(repository, |(_, rest)| rest)
+        .trim_end_matches('/');
+    let trimmed = trimmed.strip_suffix(".git").unwrap_or(trimmed);
+    let readable = trimmed
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let hash = cache::hex(&Sha256::digest(trimmed.as_bytes()));
+    format!("{}-{}", readable.trim_matches(['_', '.']), &hash[..12])
+}
+
+/// Whether the package's git cache has its ref, so it can be checked out
+/// without the network.
+pub fn has(spec: &PackageSpec) -> Result<bool> {
+    let sources::Source::Git(urls) = &spec.source else {
+        return Ok(false);
+    };
+    let Some(repository) = urls.first() else {
+        return Ok(false);
+    };
+    Ok(Mirror::for_repository(repository)?
+        .resolve(&spec.git_ref)
+        .is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The readable part of the name, without the hash.
+    fn readable(url: &str) -> String {
+        let name = mirror_name(url);
+        name.rsplit_once('-').unwrap().0.to_string()
+    }
+
+    #[test]
+    fn names_mirrors_after_their_url() {
+        for (url, name) in [
+            (
+                "https://github.com/open-mmlab/mmcv.git",
+                "github.com_open-mmlab_mmcv",
+            ),
+            (
+                "https://github.com/open-mmlab/mmengine",
+                "github.com_open-mmlab_mmengine",
+            ),
+            (
+                "https://gitee.com/open-mmlab/mmcv.git/",
+                "gitee.com_open-mmlab_mmcv",
+            ),
+            ("git@github.com:me/mmcv.git", "git_github.com_me_mmcv"),
+            ("file:///srv/git/mmcv.git", "srv_git_mmcv"),
+            ("/srv/git/../mmcv", "srv_git_.._mmcv"),
+        ] {
+            assert_eq!(readable(url), name, "{url}");
+        }
+    }
+
+    #[test]
+    fn shares_a_mirror_between_spellings_of_one_url() {
+        assert_eq!(
+            mirror_name("https://github.com/open-mmlab/mmcv.git"),
+            mirror_name("https://github.com/open-mmlab/mmcv/")
+        );
+    }
+
+    #[test]
+    fn keeps_different_repositories_apart() {
+        assert_ne!(
+            mirror_name("https://github.com/open-mmlab/mmcv.git"),
+            mirror_name("https://github.com/me/mmcv.git")
+        );
+        // Same readable name, told apart only by the hash.
+        for (first, second) in [
+            ("https://host/a_b/c.git", "https://host/a/b_c.git"),
+            ("git@host:a/b.git", "git@host/a/b.git"),
+            ("https://host/a:b/c", "https://host/a@b/c"),
+        ] {
+            assert_eq!(readable(first), readable(second), "{first}");
+            assert_ne!(mirror_name(first), mirror_name(second), "{first}");
+        }
+    }
+}
@@ -1,48 +1,324 @@
-use std::fs;
+use std::cell::OnceCell;
+use std::fs::{self, File, TryLockError};
+use std::io;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, bail};
-use clap::Parser;
-use console::style;
-use glob::glob;
+use clap::{Args, Parser, Subcommand};
+use console::{Style, style};
 use indicatif::{ProgressBar, ProgressStyle};
 
-const MMC_VERSION: &str = "2.1.0";
-const MMACTION_VERSION: &str = "1.2.0";
-const MMENGINE_VERSION: &str = "0.10.7";
+use crate::bundle::{Bundle, DEFAULT_BUNDLE_FILE};
+use crate::cache::BuildTarget;
+use crate::compat::Severity;
+use crate::config::{BuildVariant, Config, PYPROJECT_FILE};
+use crate::fixes::{Catalog, USER_CATALOG_FILE};
+use crate::manifest::Manifest;
+use crate::pipeline::PackageSpec;
+use crate::wheel_store::WheelStore;
+
+mod bundle;
+mod cache;
+mod commands;
+mod compat;
+mod config;
+mod diff;
+mod doctor;
+mod fixes;
+mod git_cache;
+mod manifest;
+mod offline;
+mod patch;
+mod pipeline;
+mod sources;
+mod torch;
+mod torch_load;
+mod wheel_store;
+
 const WHEELHOUSE: &str = ".wheelhouse";
+/// Checkouts used by `patch check`, one per package and tag.
+const CHECK_DIR: &str = ".patch-check";
+/// Per-run directories for `--debug` artifacts such as the patch diffs.
+const LOG_DIR: &str = ".setup-logs";
+const DEFAULT_PYTHON: &str = "3.12";
 
 #[derive(Parser, Debug)]
 #[command(
     name = "setup",
     author,
     version,
-    about = "Install mmaction stack with local wheel builds and run uv sync"
+    about = "Install mmaction stack with local wheel builds and run uv sync",
+    args_conflicts_with_subcommands = true
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    install: InstallArgs,
+
+    #[command(flatten)]
+    options: Options,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "Build wheels, install them into the venv and run uv sync (default)")]
+    Install(InstallArgs),
+
+    #[command(about = "Build wheels into .wheelhouse without installing them or running uv sync")]
+    Build,
+
+    #[command(about = "Report which wheels are built and which packages are installed")]
+    Status,
+
+    #[command(about = "Remove cached wheels and/or cloned package sources")]
+    Clean(CleanArgs),
+
+    #[command(about = "Check the tools the installer and the builds depend on")]
+    Doctor,
+
+    #[command(about = "Work with the manifest patches and diff files")]
+    Patch(PatchArgs),
+
+    #[command(
+        about = "Download Python, torch, dependencies, sources, patches and uv into a bundle for offline installs"
+    )]
+    Prefetch(PrefetchArgs),
+}
+
+#[derive(Args, Debug)]
+struct PrefetchArgs {
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = DEFAULT_BUNDLE_FILE,
+        help = "Where to write the bundle archive"
+    )]
+    output: PathBuf,
+
     #[arg(
         long,
         default_value_t = false,
+        help = "Also include the wheels in .wheelhouse for the selected versions"
+    )]
+    with_wheels: bool,
+}
+
+#[derive(Args, Debug)]
+struct PatchArgs {
+    #[command(subcommand)]
+    command: PatchCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum PatchCommands {
+    #[command(
+        about = "Apply the patches to a checkout of an upstream tag and show the diff, without building"
+    )]
+    Check(PatchCheckArgs),
+}
+
+#[derive(Args, Debug)]
+struct PatchCheckArgs {
+    #[arg(
+        long = "package",
+        value_name = "NAME",
+        value_delimiter = ',',
+        help = "Only check these packages (defaults to every manifest package with patches)"
+    )]
+    packages: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "TAG",
+        help = "Check against this tag instead of the selected version's (needs exactly one --package)"
+    )]
+    tag: Option<String>,
+}
+
+#[derive(Args, Debug, Default)]
+struct InstallArgs {
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Delete .wheelhouse and the package source directories before reinstalling"
+    )]
+    purge: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Install from a bundle written by `prefetch`, without network access"
+    )]
+    from_bundle: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct CleanArgs {
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Remove built wheels from .wheelhouse"
+    )]
+    wheels: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Remove cloned package source directories"
+    )]
+    sources: bool,
+
+    #[arg(
+        long = "package",
+        value_name = "NAME",
+        value_delimiter = ',',
+        help = "Only clean these packages (defaults to every manifest package)"
+    )]
+    packages: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Options {
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
         help = "Show command output while running setup"
     )]
     debug: bool,
 
     #[arg(
         long,
+        global = true,
         default_value_t = false,
-        help = "Delete .wheelhouse, .mmaction2, .mmengine, and .mmcv before reinstalling"
+        help = "Print the planned steps and commands without changing anything"
     )]
-    purge: bool,
+    dry_run: bool,
 
     #[arg(
         long,
+        global = true,
+        value_name = "PATH",
+        help = "Package manifest (defaults to ./mmaction-install.toml, then the bundled manifest)"
+    )]
+    manifest: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
         value_name = "PATH",
         help = "Virtual environment path for uv (relative or absolute)"
     )]
     venv: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "VERSION",
+        help = "Python version used when creating the virtual environment [default: 3.12]"
+    )]
+    python: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "VARIANT",
+        help = "Build variant for compiled extensions [default: auto]"
+    )]
+    build_variant: Option<BuildVariant>,
+
+    #[arg(
+        long = "with",
+        global = true,
+        value_name = "PACKAGE",
+        value_delimiter = ',',
+        help = "Also build optional packages (mmdet, mmpose, mmpretrain, mmsegmentation)"
+    )]
+    with: Vec<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "MMCV_VERSION",
+        value_name = "VERSION",
+        help = "Build and install this mmcv version instead of the manifest pin"
+    )]
+    mmcv_version: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "MMACTION_VERSION",
+        value_name = "VERSION",
+        help = "Build and install this mmaction2 version instead of the manifest pin"
+    )]
+    mmaction_version: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "MMENGINE_VERSION",
+        value_name = "VERSION",
+        help = "Build and install this mmengine version instead of the manifest pin"
+    )]
+    mmengine_version: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Report incompatible package/torch/Python versions as warnings instead of failing"
+    )]
+    ignore_compat: bool,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Skip the compiler/torch/disk preflight checks that run before building"
+    )]
+    skip_preflight: bool,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Also fail the build when a patch is not applicable (failed patches always stop it)"
+    )]
+    strict_patches: bool,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Use only cached sources, a local uv and the wheelhouse; never touch the network"
+    )]
+    offline: bool,
+
+    #[arg(
+        long,
+        global = true,
+        env = "MMACTION_WHEEL_STORE",
+        value_name = "DIR",
+        help = "Shared wheel store to take built wheels from and publish them to"
+    )]
+    wheel_store: Option<PathBuf>,
+}
+
+impl Options {
+    fn version_overrides(&self) -> [(&'static str, Option<&str>); 3] {
+        [
+            ("mmcv", self.mmcv_version.as_deref()),
+            ("mmaction2", self.mmaction_version.as_deref()),
+            ("mmengine", self.mmengine_version.as_deref()),
+        ]
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -53,14 +329,181 @@ enum OutputMode {
 
 struct App {
     debug: bool,
+    dry_run: bool,
+    purge: bool,
     venv_dir: PathBuf,
     venv_was_provided: bool,
+    python_version: String,
+    build_variant: BuildVariant,
+    manifest: Manifest,
+    packages: Vec<PackageSpec>,
+    catalog: Catalog,
+    bundle: Option<Bundle>,
+    ignore_compat: bool,
+    skip_preflight: bool,
+    strict_patches: bool,
+    offline: bool,
+    wheel_store: Option<WheelStore>,
+    manifest_origin: String,
+    config_found: bool,
+    build_target: OnceCell<BuildTarget>,
+    run_log_dir: PathBuf,
 }
 
 impl App {
+    fn load(
+        options: &Options,
+        config: Config,
+        config_found: bool,
+        purge: bool,
+        bundle: Option<Bundle>,
+    ) -> Result<Self> {
+        let (venv_dir, venv_was_provided) =
+            resolve_venv_path(options.venv.clone().or(config.venv.clone()))?;
+        let (manifest, manifest_origin, catalog, patch_dir) = match &bundle {
+            // A bundle pins everything it installs; only the venv side is
+            // still configurable.
+            Some(bundle) => {
+                if options.manifest.is_some()
+                    || !options.with.is_empty()
+                    || options.version_overrides().iter().any(|(_, v)| v.is_some())
+                {
+                    bail!(
+                        "--from-bundle installs the packages and versions the bundle was made with; drop --manifest, --with and the version overrides"
+                    );
+                }
+                (
+                    Manifest::from_file(&bundle.manifest_path())?,
+                    format!("bundle {}", bundle.archive.display()),
+                    Catalog::load(&bundle.fixes_path())?,
+                    bundle.patch_dir(),
+                )
+            }
+            None => {
+                let (mut manifest, manifest_origin) =
+                    Manifest::load(options.manifest.as_deref().or(config.manifest.as_deref()))?;
+                config.apply_to_manifest(&mut manifest)?;
+                let mut enabled = config.with.clone();
+                enabled.extend(options.with.iter().cloned());
+                manifest.select_optional(&enabled)?;
+                apply_version_overrides(&mut manifest, options)?;
+                (
+                    manifest,
+                    manifest_origin,
+                    Catalog::load(Path::new(USER_CATALOG_FILE))?,
+                    PathBuf::from(diff::USER_PATCH_DIR),
+                )
+            }
+        };
+
+        Ok(Self {
+            debug: options.debug || config.debug.unwrap_or(false),
+            dry_run: options.dry_run,
+            purge,
+            venv_dir,
+            venv_was_provided,
+            python_version: options
+                .python
+                .clone()
+                .or(config.python)
+                .unwrap_or_else(|| DEFAULT_PYTHON.to_string()),
+            build_variant: options
+                .build_variant
+                .or(config.build_variant)
+                .unwrap_or_default(),
+            packages: manifest
+                .packages
+                .iter()
+                .map(|package| PackageSpec::from_manifest(package, &catalog, &patch_dir))
+                .collect::<Result<_>>()?,
+            manifest,
+            catalog,
+            ignore_compat: options.ignore_compat,
+            skip_preflight: options.skip_preflight,
+            strict_patches: options.strict_patches || config.strict_patches.unwrap_or(false),
+            offline: options.offline || config.offline.unwrap_or(false) || bundle.is_some(),
+            wheel_store: options
+                .wheel_store
+                .clone()
+                .or(config.wheel_store)
+                .map(WheelStore::new),
+            bundle,
+            manifest_origin,
+            config_found,
+            build_target: OnceCell::new(),
+            run_log_dir: Path::new(LOG_DIR).join(run_id()),
+        })
+    }
+
     fn python_bin(&self) -> PathBuf {
         self.venv_dir.join("bin/python")
     }
+
+    /// Detected on first use, which must come after torch is installed.
+    fn build_target(&self) -> Result<&BuildTarget> {
+        if let Some(target) = self.build_target.get() {
+            return Ok(target);
+        }
+        let target = BuildTarget::detect(self)?;
+        Ok(self.build_target.get_or_init(|| target))
+    }
+
+    fn verbose(&self) -> bool {
+        self.debug || self.dry_run
+    }
+
+    fn plan(&self, message: &str) {
+        if self.dry_run {
+            println!("  {} {}", style("•").cyan(), style(message).dim());
+        }
+    }
+
+    fn finish(&self, message: &str) {
+        let message = if self.dry_run {
+            "Dry run complete; nothing was changed."
+        } else {
+            message
+        };
+        println!(
+            "{} {}",
+            style("✔").green().bold(),
+            style(message).green().bold()
+        );
+    }
+}
+
+/// Numbers and runs the steps of a command through `run_step`.
+struct Steps {
+    current: usize,
+    total: usize,
+    verbose: bool,
+}
+
+impl Steps {
+    fn new(total: usize, verbose: bool) -> Self {
+        Self {
+            current: 1,
+            total,
+            verbose,
+        }
+    }
+
+    fn run<F>(&mut self, name: &str, f: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let verbose = self.verbose;
+        self.run_with(name, verbose, f)
+    }
+
+    fn run_with<F>(&mut self, name: &str, verbose: bool, f: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let index = self.current;
+        self.current += 1;
+        run_step(index, self.total, name, verbose, f)
+    }
 }
 
 fn main() {
@@ -72,97 +515,61 @@ fn main() {
 
 fn run() -> Result<()> {
     let cli = Cli::parse();
-    let (venv_dir, venv_was_provided) = resolve_venv_path(cli.venv)?;
-    let app = App {
-        debug: cli.debug,
-        venv_dir,
-        venv_was_provided,
+    let (config, config_found) = Config::load()?;
+
+    let command = cli.command.unwrap_or(Commands::Install(cli.install));
+    let purge = matches!(&command, Commands::Install(args) if args.purge);
+    if config.purge == Some(true) {
+        print_warnings(&[
+            "purge in [tool.mmaction-install] is ignored; it would wipe everyone's wheelhouse on \
+             every install, so pass --purge when you mean it"
+                .to_string(),
+        ]);
+    }
+    let bundle = match &command {
+        Commands::Install(InstallArgs {
+            from_bundle: Some(archive),
+            ..
+        }) => Some(Bundle::unpack(archive, cli.options.dry_run)?),
+        _ => None,
     };
-    let total_steps = if cli.purge { 9 } else { 8 };
-    let mut step = 1;
-
-    print_header(&app);
-
-    if cli.purge {
-        run_step(
-            step,
-            total_steps,
-            "Purging mmaction cache directories",
-            cli.debug,
-            || purge_cache_dirs(),
-        )?;
-        step += 1;
-    }
-
-    run_step(
-        step,
-        total_steps,
-        "Ensuring wheelhouse directory",
-        cli.debug,
-        || fs::create_dir_all(WHEELHOUSE).context("failed to create .wheelhouse directory"),
-    )?;
-    step += 1;
-
-    run_step(
-        step,
-        total_steps,
-        "Ensuring uv availability",
-        cli.debug,
-        || ensure_uv(&app),
-    )?;
-    step += 1;
-
-    run_step(
-        step,
-        total_steps,
-        "Ensuring Python virtual environment",
-        cli.debug,
-        || ensure_venv(&app),
-    )?;
-    step += 1;
-
-    run_step(step, total_steps, "Ensuring pip tooling", cli.debug, || {
-        ensure_pip_tooling(&app)
-    })?;
-    step += 1;
-
-    run_step(
-        step,
-        total_steps,
-        "Building/installing mmcv",
-        cli.debug,
-        || build_and_install_mmcv(&app),
-    )?;
-    step += 1;
-
-    run_step(
-        step,
-        total_steps,
-        "Building/installing mmaction2",
-        cli.debug,
-        || build_and_install_mmaction2(&app),
-    )?;
-    step += 1;
-
-    run_step(
-        step,
-        total_steps,
-        "Building/installing mmengine",
-        cli.debug,
-        || build_and_install_mmengine(&app),
-    )?;
-    step += 1;
-
-    run_step(step, total_steps, "Running uv sync", true, || {
-        run_uv_sync(&app)
-    })?;
+    let app = App::load(&cli.options, config, config_found, purge, bundle)?;
+    if app.offline {
+        // Covers every uv and pip invocation, including the ones uv runs.
+        unsafe {
+            std::env::set_var("UV_OFFLINE", "1");
+            std::env::set_var("PIP_NO_INDEX", "1");
+        }
+    }
 
-    println!(
-        "{} {}",
-        style("✔").green().bold(),
-        style("Setup completed successfully.").green().bold()
-    );
+    match command {
+        Commands::Install(_) => commands::install(&app),
+        Commands::Build => commands::build(&app),
+        Commands::Status => commands::status(&app),
+        Commands::Clean(args) => commands::clean(&app, args.wheels, args.sources, &args.packages),
+        Commands::Doctor => commands::doctor(&app),
+        Commands::Patch(PatchArgs {
+            command: PatchCommands::Check(args),
+        }) => commands::patch_check(&app, &args.packages, args.tag.as_deref()),
+        Commands::Prefetch(args) => commands::prefetch(&app, &args.output, args.with_wheels),
+    }
+}
 
+/// The run's start time, `20261016T093000Z`, naming its log directory.
+fn run_id() -> String {
+    cache::utc_timestamp().replace(['-', ':'], "")
+}
+
+fn apply_version_overrides(manifest: &mut Manifest, options: &Options) -> Result<()> {
+    for (name, version) in options.version_overrides() {
+        let Some(version) = version else {
+            continue;
+        };
+        let Some(package) = manifest.packages.iter_mut().find(|p| p.name == name) else {
+            bail!("cannot override {name} version: {name} is not in the manifest");
+        };
+        package.set_version(version);
+    }
     Ok(())
 }
 
@@ -187,6 +594,130 @@ fn print_header(app: &App) {
         style("Virtual env:").dim(),
         style(app.venv_dir.display()).dim()
     );
+    println!(
+        "{} {} {}",
+        style("•").cyan(),
+        style("Manifest:").dim(),
+        style(&app.manifest_origin).dim()
+    );
+    if let Some(bundle) = &app.bundle {
+        println!(
+            "{} {} {}",
+            style("•").cyan(),
+            style("Bundle:").dim(),
+            style(format!(
+                "created {}, {} wheel(s), {} dependency wheel(s), {}, {}",
+                bundle.info.created_at,
+                bundle.info.wheels.len(),
+                bundle.info.dependencies.len(),
+                bundle.info.python.as_deref().unwrap_or("no Python"),
+                bundle.info.uv.as_deref().unwrap_or("no uv")
+            ))
+            .dim()
+        );
+    }
+    println!(
+        "{} {} {}",
+        style("•").cyan(),
+        style("Packages:").dim(),
+        style(
+            app.packages
+                .iter()
+                .map(|package| format!("{} {}", package.name, package.version))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+        .dim()
+    );
+    let fixes: Vec<String> = app
+        .packages
+        .iter()
+        .flat_map(|package| {
+            package
+                .fixes
+                .iter()
+                .map(move |fix| format!("{} {}", package.name, fix.id))
+        })
+        .collect();
+    if !fixes.is_empty() {
+        println!(
+            "{} {} {}",
+            style("•").cyan(),
+            style("Known fixes:").dim(),
+            style(fixes.join(", ")).dim()
+        );
+    }
+    println!(
+        "{} {} {}",
+        style("•").cyan(),
+        style("Python / build variant:").dim(),
+        style(format!(
+            "{} / {}",
+            app.python_version,
+            app.build_variant.as_str()
+        ))
+        .dim()
+    );
+    if app.dry_run {
+        println!(
+            "{} {}",
+            style("•").cyan(),
+            style("Dry run: commands are printed, not executed").yellow()
+        );
+    }
+    if app.offline {
+        println!(
+            "{} {}",
+            style("•").cyan(),
+            style("Offline: only cached sources, the wheelhouse and uv's cache are used").yellow()
+        );
+    }
+    if let Some(store) = &app.wheel_store {
+        println!(
+            "{} {} {}",
+            style("•").cyan(),
+            style("Wheel store:").dim(),
+            style(store.root().display()).dim()
+        );
+    }
+    if app.config_found {
+        println!(
+            "{} {} {}",
+            style("•").cyan(),
+            style("Config:").dim(),
+            style(format!("{PYPROJECT_FILE} [tool.mmaction-install]")).dim()
+        );
+    }
+}
+
+/// Prints left-aligned columns; `styles[i]` (when present) colors row `i`.
+fn print_table<const N: usize>(headers: [&str; N], rows: &[[String; N]], styles: &[Style]) {
+    let mut widths = headers.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header: Vec<String> = headers
+        .iter()
+        .zip(widths)
+        .map(|(header, width)| format!("{header:<width$}"))
+        .collect();
+    println!("{}", style(header.join("  ").trim_end()).bold());
+
+    for (index, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        let line = cells.join("  ");
+        match styles.get(index) {
+            Some(row_style) => println!("{}", row_style.apply_to(line.trim_end())),
+            None => println!("{}", line.trim_end()),
+        }
+    }
 }
 
 fn run_step<F>(index: usize, total: usize, name: &str, debug: bool, f: F) -> Result<()>
@@ -296,8 +827,22 @@ fn resolve_venv_path(venv: Option<PathBuf>) -> Result<(PathBuf, bool)> {
     Ok((venv_dir, venv_was_provided))
 }
 
+fn ensure_wheelhouse(app: &App) -> Result<()> {
+    let purged = app.dry_run && app.purge;
+    if Path::new(WHEELHOUSE).is_dir() && !purged {
+        app.plan(&format!("{WHEELHOUSE} already exists"));
+        return Ok(());
+    }
+    if app.dry_run {
+        app.plan(&format!("would create {WHEELHOUSE}"));
+        return Ok(());
+    }
+    fs::create_dir_all(WHEELHOUSE).context("failed to create .wheelhouse directory")
+}
+
 fn ensure_uv(app: &App) -> Result<()> {
     if uv_is_available() {
+        app.plan("uv is available on PATH");
         return Ok(());
     }
 
@@ -310,6 +855,12 @@ fn ensure_uv(app: &App) -> Result<()> {
         }
     }
 
+    if app.offline {
+        bail!(
+            "uv is not on PATH, in ~/.local/bin or in ~/.cargo/bin, and --offline cannot download it"
+        );
+    }
+
     if command_exists("curl") {
         let mut command = Command::new("sh");
         command.args(["-c", "curl -LsSf https://astral.sh/uv/install.sh | sh"]);
@@ -324,6 +875,10 @@ fn ensure_uv(app: &App) -> Result<()> {
         );
     }
 
+    if app.dry_run {
+        return Ok(());
+    }
+
     for candidate_dir in uv_candidate_dirs() {
         if candidate_dir.join("uv").exists() {
             prepend_path_dir(&candidate_dir)?;
@@ -350,19 +905,21 @@ fn uv_is_available() -> bool {
 }
 
 fn command_exists(name: &str) -> bool {
-    let Some(path_var) = std::env::var_os("PATH") else {
-        return false;
-    };
+    find_program(name).is_some()
+}
 
-    std::env::split_paths(&path_var).any(|dir| {
-        let candidate = dir.join(name);
-        if !candidate.is_file() {
-            return false;
-        }
-        fs::metadata(candidate)
-            .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
-            .unwrap_or(false)
-    })
+/// The first executable `name` on PATH.
+fn find_program(name: &str) -> Option<PathBuf> {
+    let path_var = std::env::var_os("PATH")?;
+
+    std::env::split_paths(&path_var)
+        .map(|dir| dir.join(name))
+        .find(|candidate| {
+            candidate.is_file()
+                && fs::metadata(candidate)
+                    .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false)
+        })
 }
 
 fn uv_candidate_dirs() -> Vec<PathBuf> {
@@ -403,8 +960,15 @@ fn prepend_path_dir(dir: &Path) -> Result<()> {
 fn ensure_venv(app: &App) -> Result<()> {
     let python_bin = app.python_bin();
 
-    if !python_bin.exists() {
-        if let Some(parent) = app.venv_dir.parent() {
+    if python_bin.exists() {
+        app.plan(&format!(
+            "virtual environment already exists at {}",
+            app.venv_dir.display()
+        ));
+    } else {
+        if let Some(parent) = app.venv_dir.parent()
+            && !app.dry_run
+        {
             fs::create_dir_all(parent).with_context(|| {
                 format!(
                     "failed to create parent directory for venv: {}",
@@ -417,7 +981,7 @@ fn ensure_venv(app: &App) -> Result<()> {
         command
             .arg("venv")
             .arg("--python")
-            .arg("3.12")
+            .arg(&app.python_version)
             .arg(&app.venv_dir);
         run_command(
             app,
@@ -432,14 +996,22 @@ fn ensure_venv(app: &App) -> Result<()> {
 fn ensure_pip_tooling(app: &App) -> Result<()> {
     let python_bin = app.python_bin();
 
-    let import_status = Command::new(&python_bin)
-        .args(["-c", "import pip"])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .context("failed to run python pip import check")?;
+    let has_pip = if python_bin.exists() {
+        Command::new(&python_bin)
+            .args(["-c", "import pip"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("failed to run python pip import check")?
+            .success()
+    } else {
+        // Only reachable in dry-run mode, where the venv is not created.
+        false
+    };
 
-    if !import_status.success() {
+    if has_pip {
+        app.plan("pip is already installed in the venv");
+    } else {
         let mut command = Command::new("uv");
         command
             .arg("pip")
@@ -455,160 +1027,70 @@ fn ensure_pip_tooling(app: &App) -> Result<()> {
     Ok(())
 }
 
-fn build_and_install_mmcv(app: &App) -> Result<()> {
-    if !wheel_exists("mmcv", MMC_VERSION)? {
-        remove_dir_if_exists(".mmcv")?;
+fn query_python(app: &App, script: &str) -> Result<Option<String>> {
+    let python_bin = app.python_bin();
+    if !python_bin.exists() {
+        return Ok(None);
+    }
+
+    let output = Command::new(python_bin)
+        .args(["-c", script])
+        .stdin(Stdio::null())
+        .output()
+        .context("failed to run python in the virtual environment")?;
 
-        let mut clone = Command::new("git");
-        clone.args([
-            "clone",
-            "--depth",
-            "1",
-            "--branch",
-            &format!("v{MMC_VERSION}"),
-            "https://github.com/open-mmlab/mmcv.git",
-            ".mmcv",
-        ]);
-        run_command(app, "clone mmcv", clone, OutputMode::Quiet)?;
-
-        remove_dir_if_exists(".mmcv/.git")?;
-
-        let python_bin = app.python_bin();
-        let mut wheel = Command::new(&python_bin);
-        wheel.args([
-            "-m",
-            "pip",
-            "wheel",
-            "-v",
-            "./.mmcv",
-            "--no-deps",
-            "--no-build-isolation",
-            "--wheel-dir",
-            WHEELHOUSE,
-        ]);
-        run_command(app, "build mmcv wheel", wheel, OutputMode::Quiet)?;
+    if !output.status.success() {
+        return Ok(None);
     }
 
-    let mut install = Command::new("uv");
-    let python_bin = app.python_bin();
-    install
-        .arg("pip")
-        .arg("install")
-        .arg("-v")
-        .arg("--python")
-        .arg(&python_bin)
-        .arg("--no-deps")
-        .arg("--no-index")
-        .arg("--find-links")
-        .arg(WHEELHOUSE)
-        .arg(format!("mmcv=={MMC_VERSION}"));
-    run_command(app, "install mmcv", install, OutputMode::Quiet)
-}
-
-fn build_and_install_mmaction2(app: &App) -> Result<()> {
-    if !wheel_exists("mmaction2", MMACTION_VERSION)? {
-        remove_dir_if_exists(".mmaction2")?;
-
-        let mut clone = Command::new("git");
-        clone.args([
-            "clone",
-            "--depth",
-            "1",
-            "--branch",
-            &format!("v{MMACTION_VERSION}"),
-            "https://github.com/open-mmlab/mmaction2.git",
-            ".mmaction2",
-        ]);
-        run_command(app, "clone mmaction2", clone, OutputMode::Quiet)?;
-
-        remove_dir_if_exists(".mmaction2/.git")?;
-
-        patch_torch_load_single_line(".mmaction2/mmaction/apis/inference.py")?;
-        patch_get_version_function(".mmaction2/setup.py", MMACTION_VERSION)?;
-
-        let python_bin = app.python_bin();
-        let mut wheel = Command::new(&python_bin);
-        wheel.args([
-            "-m",
-            "pip",
-            "wheel",
-            "-v",
-            "./.mmaction2",
-            "--no-deps",
-            "--no-build-isolation",
-            "--wheel-dir",
-            WHEELHOUSE,
-        ]);
-        run_command(app, "build mmaction2 wheel", wheel, OutputMode::Quiet)?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!value.is_empty()).then_some(value))
+}
+
+fn venv_python_version(app: &App) -> Result<Option<String>> {
+    query_python(app, "import sys; print('%d.%d.%d' % sys.version_info[:3])")
+}
+
+fn venv_torch_version(app: &App) -> Result<Option<String>> {
+    query_python(app, "import torch; print(torch.__version__)")
+}
+
+fn check_compatibility(app: &App, warnings: &mut Vec<String>) -> Result<()> {
+    let python = venv_python_version(app)?;
+    let torch = venv_torch_version(app)?;
+    if torch.is_none() {
+        warnings.push("torch is not installed in the venv yet; skipped torch constraints".into());
     }
 
-    let mut install = Command::new("uv");
-    let python_bin = app.python_bin();
-    install
-        .arg("pip")
-        .arg("install")
-        .arg("-v")
-        .arg("--python")
-        .arg(&python_bin)
-        .arg("--no-deps")
-        .arg("--no-index")
-        .arg("--find-links")
-        .arg(WHEELHOUSE)
-        .arg(format!("mmaction2=={MMACTION_VERSION}"));
-    run_command(app, "install mmaction2", install, OutputMode::Quiet)
-}
-
-fn build_and_install_mmengine(app: &App) -> Result<()> {
-    if !wheel_exists("mmengine", MMENGINE_VERSION)? {
-        remove_dir_if_exists(".mmengine")?;
-
-        let mut clone = Command::new("git");
-        clone.args([
-            "clone",
-            "--depth",
-            "1",
-            "--branch",
-            &format!("v{MMENGINE_VERSION}"),
-            "https://github.com/open-mmlab/mmengine",
-            ".mmengine",
-        ]);
-        run_command(app, "clone mmengine", clone, OutputMode::Quiet)?;
-
-        remove_dir_if_exists(".mmengine/.git")?;
-
-        patch_get_version_function(".mmengine/setup.py", MMENGINE_VERSION)?;
-        patch_torch_load_single_line(".mmengine/mmengine/runner/checkpoint.py")?;
-
-        let python_bin = app.python_bin();
-        let mut wheel = Command::new(&python_bin);
-        wheel.args([
-            "-m",
-            "pip",
-            "wheel",
-            "-v",
-            "./.mmengine",
-            "--no-deps",
-            "--no-build-isolation",
-            "--wheel-dir",
-            WHEELHOUSE,
-        ]);
-        run_command(app, "build mmengine wheel", wheel, OutputMode::Quiet)?;
+    let violations = compat::check(&app.packages, torch.as_deref(), python.as_deref())?;
+    let (errors, others): (Vec<_>, Vec<_>) = violations
+        .into_iter()
+        .partition(|violation| violation.severity == Severity::Error);
+    warnings.extend(others.iter().map(ToString::to_string));
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+    if app.ignore_compat {
+        warnings.extend(errors.iter().map(ToString::to_string));
+        return Ok(());
     }
 
-    let mut install = Command::new("uv");
-    let python_bin = app.python_bin();
-    install
-        .arg("pip")
-        .arg("install")
-        .arg("-v")
-        .arg("--python")
-        .arg(&python_bin)
-        .arg("--no-deps")
-        .arg("--no-index")
-        .arg("--find-links")
-        .arg(WHEELHOUSE)
-        .arg(format!("mmengine=={MMENGINE_VERSION}"));
-    run_command(app, "install mmengine", install, OutputMode::Quiet)
+    let details: Vec<String> = errors.iter().map(|error| format!("  - {error}")).collect();
+    bail!(
+        "incompatible versions selected (rerun with --ignore-compat to continue anyway):\n{}",
+        details.join("\n")
+    )
+}
+
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        println!(
+            "  {} {}",
+            style("⚠").yellow().bold(),
+            style(warning).yellow()
+        );
+    }
 }
 
 fn run_uv_sync(app: &App) -> Result<()> {
@@ -621,15 +1103,19 @@ fn run_uv_sync(app: &App) -> Result<()> {
     } else {
         "uv sync"
     };
-    let mode = if app.debug {
-        OutputMode::Stream
-    } else {
-        OutputMode::Stream
-    };
-    run_command(app, label, command, mode)
+    run_command(app, label, command, OutputMode::Stream)
 }
 
 fn run_command(app: &App, label: &str, mut command: Command, mode: OutputMode) -> Result<()> {
+    if app.dry_run {
+        println!(
+            "  {} {}",
+            style("$").cyan(),
+            style(describe_command(&command)).bold()
+        );
+        return Ok(());
+    }
+
     let should_stream = app.debug || matches!(mode, OutputMode::Stream);
 
     if should_stream {
@@ -669,100 +1155,85 @@ fn run_command(app: &App, label: &str, mut command: Command, mode: OutputMode) -
     bail!("command failed ({label}) with status {}", output.status)
 }
 
-fn wheel_exists(name: &str, version: &str) -> Result<bool> {
-    let pattern = format!("{WHEELHOUSE}/{name}-{version}-*.whl");
-    let mut entries = glob(&pattern).with_context(|| format!("invalid glob pattern: {pattern}"))?;
-    Ok(entries.next().transpose()?.is_some())
+fn describe_command(command: &Command) -> String {
+    let mut parts: Vec<String> = command
+        .get_envs()
+        .filter_map(|(key, value)| {
+            value.map(|value| format!("{}={}", key.to_string_lossy(), value.to_string_lossy()))
+        })
+        .collect();
+    parts.push(command.get_program().to_string_lossy().into_owned());
+    parts.extend(
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned()),
+    );
+    parts
+        .iter()
+        .map(|part| shell_quote(part))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-fn remove_dir_if_exists(path: &str) -> Result<()> {
-    let dir = PathBuf::from(path);
-    if dir.exists() {
-        fs::remove_dir_all(&dir)
-            .with_context(|| format!("failed to remove directory: {}", dir.display()))?;
+fn shell_quote(value: &str) -> String {
+    let is_plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if is_plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
     }
-    Ok(())
 }
 
-fn purge_cache_dirs() -> Result<()> {
-    for path in [WHEELHOUSE, ".mmaction2", ".mmengine", ".mmcv"] {
-        remove_dir_if_exists(path)?;
+/// Opens `path`, creating it, and takes an exclusive lock on it that lasts
+/// until the file is dropped. Waits for another process holding it. A lock
+/// file that exists is opened read-only, which is all flock needs, so users
+/// sharing a cache can lock files another user created.
+fn lock_exclusive(app: &App, path: &Path, what: &str) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
     }
-    Ok(())
-}
-
-fn patch_get_version_function(path: &str, version: &str) -> Result<()> {
-    let content = fs::read_to_string(path).with_context(|| format!("failed reading {path}"))?;
-    let mut lines: Vec<String> = content.lines().map(ToString::to_string).collect();
-
-    let Some(index) = lines
-        .iter()
-        .position(|line| line.trim_end() == "def get_version():")
-    else {
-        return Ok(());
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("failed to create lock file {}", path.display()))?,
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("failed to open lock file {}", path.display()));
+        }
     };
-
-    if lines.len() < index + 4 {
-        return Ok(());
-    }
-
-    lines.splice(
-        index..index + 4,
-        [
-            "def get_version():".to_string(),
-            format!("    return '{version}'"),
-        ],
-    );
-
-    let mut rewritten = lines.join("\n");
-    rewritten.push('\n');
-    fs::write(path, rewritten).with_context(|| format!("failed writing {path}"))?;
-    Ok(())
-}
-
-fn patch_torch_load_single_line(path: &str) -> Result<()> {
-    let content = fs::read_to_string(path).with_context(|| format!("failed reading {path}"))?;
-    let mut replaced_any = false;
-    let mut patched = Vec::with_capacity(content.lines().count());
-
-    for line in content.lines() {
-        let mut current = line.to_string();
-        let mut search_from = 0usize;
-
-        loop {
-            let Some(relative_start) = current[search_from..].find("torch.load(") else {
-                break;
-            };
-            let start = search_from + relative_start;
-            let open_paren = start + "torch.load".len();
-            let rest = &current[open_paren + 1..];
-            let Some(close_rel) = rest.find(')') else {
-                break;
-            };
-
-            let close_idx = open_paren + 1 + close_rel;
-            let args = &current[open_paren + 1..close_idx];
-
-            if args.contains("weights_only=") {
-                search_from = close_idx + 1;
-                continue;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            if app.verbose() {
+                println!(
+                    "  {} {}",
+                    style("•").cyan(),
+                    style(format!("waiting for another install to release {what}")).dim()
+                );
             }
-
-            current.insert_str(close_idx, ", weights_only=False");
-            replaced_any = true;
-            search_from = close_idx + ", weights_only=False".len() + 1;
+            file.lock()
+                .with_context(|| format!("failed to lock {}", path.display()))?;
+        }
+        Err(TryLockError::Error(error)) => {
+            return Err(error).with_context(|| format!("failed to lock {}", path.display()));
         }
-
-        patched.push(current);
     }
+    Ok(file)
+}
 
-    if !replaced_any {
-        return Ok(());
+fn remove_dir_if_exists(path: impl AsRef<Path>) -> Result<()> {
+    let dir = path.as_ref();
+    if dir.exists() {
+        fs::remove_dir_all(dir)
+            .with_context(|| format!("failed to remove directory: {}", dir.display()))?;
     }
-
-    let mut rewritten = patched.join("\n");
-    rewritten.push('\n');
-    fs::write(path, rewritten).with_context(|| format!("failed writing {path}"))?;
-
     Ok(())
 }
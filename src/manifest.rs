@@ -0,0 +1,392 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MANIFEST_FILE: &str = "mmaction-install.toml";
+const BUNDLED_MANIFEST: &str = include_str!("../manifests/default.toml");
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(rename = "package", default)]
+    pub packages: Vec<PackageManifest>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub repository: String,
+    /// Git URLs tried in order when cloning `repository` fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// A local source tree, used instead of cloning.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// A source archive (`.tar.gz`, `.tar.xz`, ...), used instead of cloning.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tarball: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Build the tip of this branch instead of a tag.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// The commit the tag or branch must resolve to; on its own, the commit
+    /// to build.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<String>,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default)]
+    pub patches: Vec<PatchSpec>,
+    #[serde(default)]
+    pub build: BuildSettings,
+}
+
+/// The git ref a package's source is read at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitRef {
+    Tag(String),
+    Branch(String),
+    /// A full commit SHA, fetched directly.
+    Commit(String),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, tag = "kind", rename_all = "kebab-case")]
+pub enum PatchSpec {
+    TorchLoadWeightsOnly { path: String },
+    StaticVersion { path: String },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BuildSettings {
+    #[serde(default = "default_true")]
+    pub no_build_isolation: bool,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub pip_args: Vec<String>,
+}
+
+impl Default for BuildSettings {
+    fn default() -> Self {
+        Self {
+            no_build_isolation: true,
+            env: BTreeMap::new(),
+            pip_args: Vec::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl GitRef {
+    pub fn name(&self) -> &str {
+        match self {
+            GitRef::Tag(name) | GitRef::Branch(name) | GitRef::Commit(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for GitRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitRef::Tag(tag) => write!(f, "{tag}"),
+            GitRef::Branch(branch) => write!(f, "branch {branch}"),
+            GitRef::Commit(commit) => write!(f, "commit {}", short_commit(commit)),
+        }
+    }
+}
+
+pub fn short_commit(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
+
+impl PackageManifest {
+    /// The tag or branch if one is set, then a bare commit, then `v{version}`.
+    pub fn git_ref(&self) -> GitRef {
+        if let Some(tag) = &self.tag {
+            GitRef::Tag(tag.clone())
+        } else if let Some(branch) = &self.branch {
+            GitRef::Branch(branch.clone())
+        } else if let Some(commit) = &self.commit {
+            GitRef::Commit(commit.to_ascii_lowercase())
+        } else {
+            GitRef::Tag(format!("v{}", self.version))
+        }
+    }
+
+    /// The commit the fetched tree must be at, if one is pinned.
+    pub fn expected_commit(&self) -> Option<String> {
+        self.commit
+            .as_ref()
+            .map(|commit| commit.to_ascii_lowercase())
+    }
+
+    /// A new version drops the ref and commit pinned for the old one.
+    pub fn set_version(&mut self, version: &str) {
+        self.version = version.to_string();
+        self.tag = None;
+        self.branch = None;
+        self.commit = None;
+    }
+
+    pub fn source_dir(&self) -> PathBuf {
+        PathBuf::from(
+            self.directory
+                .clone()
+                .unwrap_or_else(|| format!(".{}", self.name)),
+        )
+    }
+}
+
+impl PatchSpec {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PatchSpec::TorchLoadWeightsOnly { .. } => "torch-load-weights-only",
+            PatchSpec::StaticVersion { .. } => "static-version",
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            PatchSpec::TorchLoadWeightsOnly { path } | PatchSpec::StaticVersion { path } => path,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn bundled() -> Result<Self> {
+        Self::parse(BUNDLED_MANIFEST, "bundled manifest")
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).context("failed to serialize the manifest")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed reading manifest {}", path.display()))?;
+        Self::parse(&content, &path.display().to_string())
+    }
+
+    /// Loads `explicit` if given, otherwise `mmaction-install.toml` from the
+    /// current directory, falling back to the manifest bundled in the binary.
+    pub fn load(explicit: Option<&Path>) -> Result<(Self, String)> {
+        if let Some(path) = explicit {
+            return Ok((Self::from_file(path)?, path.display().to_string()));
+        }
+
+        let local = Path::new(DEFAULT_MANIFEST_FILE);
+        if local.is_file() {
+            return Ok((Self::from_file(local)?, DEFAULT_MANIFEST_FILE.to_string()));
+        }
+
+        Ok((Self::bundled()?, "bundled".to_string()))
+    }
+
+    fn parse(content: &str, origin: &str) -> Result<Self> {
+        let manifest: Manifest =
+            toml::from_str(content).with_context(|| format!("invalid manifest: {origin}"))?;
+        manifest
+            .validate()
+            .with_context(|| format!("invalid manifest: {origin}"))?;
+        Ok(manifest)
+    }
+
+    /// Drops optional packages that were not enabled with `--with`/`with = [...]`.
+    pub fn select_optional(&mut self, enabled: &[String]) -> Result<()> {
+        for name in enabled {
+            match self.packages.iter().find(|package| &package.name == name) {
+                Some(package) if package.optional => {}
+                Some(_) => bail!("{name} is always installed; it does not need to be enabled"),
+                None => {
+                    let available: Vec<&str> = self
+                        .packages
+                        .iter()
+                        .filter(|package| package.optional)
+                        .map(|package| package.name.as_str())
+                        .collect();
+                    bail!(
+                        "unknown optional package {name} (available: {})",
+                        if available.is_empty() {
+                            "none".to_string()
+                        } else {
+                            available.join(", ")
+                        }
+                    );
+                }
+            }
+        }
+
+        self.packages
+            .retain(|package| !package.optional || enabled.contains(&package.name));
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.packages.is_empty() {
+            bail!("manifest does not list any [[package]] entries");
+        }
+
+        let mut seen = HashSet::new();
+        for package in &self.packages {
+            if package.name.trim().is_empty() {
+                bail!("package name must not be empty");
+            }
+            if package.version.trim().is_empty() {
+                bail!("package {} has an empty version", package.name);
+            }
+            if !seen.insert(package.name.as_str()) {
+                bail!("package {} is listed more than once", package.name);
+            }
+            if package.path.is_some() && package.tarball.is_some() {
+                bail!(
+                    "package {} sets both path and tarball; use one of them",
+                    package.name
+                );
+            }
+            if package.tag.is_some() && package.branch.is_some() {
+                bail!(
+                    "package {} sets both tag and branch; use one of them",
+                    package.name
+                );
+            }
+            if let Some(commit) = &package.commit {
+                if !(7..=40).contains(&commit.len())
+                    || !commit.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    bail!(
+                        "package {} commit must be a hexadecimal SHA of 7 to 40 characters: {commit}",
+                        package.name
+                    );
+                }
+                // Servers only hand out commits by their full SHA.
+                if package.tag.is_none() && package.branch.is_none() && commit.len() != 40 {
+                    bail!(
+                        "package {} builds commit {commit} directly, which needs the full 40-character SHA",
+                        package.name
+                    );
+                }
+            }
+            if package.repository.trim().is_empty()
+                && package.path.is_none()
+                && package.tarball.is_none()
+            {
+                bail!(
+                    "package {} needs a repository, a path or a tarball",
+                    package.name
+                );
+            }
+            for patch in &package.patches {
+                let path = Path::new(patch.path());
+                if path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+                    bail!(
+                        "patch path for {} must be relative to the source tree: {}",
+                        package.name,
+                        patch.path()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn mmcv() -> PackageManifest {
+        Manifest::bundled().unwrap().packages.remove(0)
+    }
+
+    fn validate(package: PackageManifest) -> Result<()> {
+        Manifest {
+            packages: vec![package],
+        }
+        .validate()
+    }
+
+    #[test]
+    fn accepts_commits_of_7_to_40_hex_digits_under_a_tag() {
+        for commit in ["0123abc", "0123ABCD", FULL] {
+            let mut package = mmcv();
+            package.tag = Some("v2.1.0".to_string());
+            package.commit = Some(commit.to_string());
+            assert!(validate(package).is_ok(), "{commit}");
+        }
+        for commit in ["0123ab", "0123abcg", &format!("{FULL}8")] {
+            let mut package = mmcv();
+            package.branch = Some("main".to_string());
+            package.commit = Some(commit.to_string());
+            let error = validate(package).unwrap_err().to_string();
+            assert!(error.contains("7 to 40 characters"), "{commit}: {error}");
+        }
+    }
+
+    #[test]
+    fn needs_the_full_sha_to_build_a_bare_commit() {
+        let mut package = mmcv();
+        package.commit = Some("0123abcd".to_string());
+        let error = validate(package).unwrap_err().to_string();
+        assert!(error.contains("full 40-character SHA"), "{error}");
+
+        let mut package = mmcv();
+        package.commit = Some(FULL.to_uppercase());
+        assert!(validate(package.clone()).is_ok());
+        assert_eq!(package.git_ref(), GitRef::Commit(FULL.to_string()));
+        assert_eq!(package.expected_commit().as_deref(), Some(FULL));
+    }
+
+    #[test]
+    fn rejects_a_tag_and_a_branch_together() {
+        let mut package = mmcv();
+        package.tag = Some("v2.1.0".to_string());
+        package.branch = Some("main".to_string());
+        assert!(validate(package).is_err());
+    }
+
+    #[test]
+    fn prefers_tag_then_branch_then_commit_then_the_version_tag() {
+        let mut package = mmcv();
+        assert_eq!(package.git_ref(), GitRef::Tag("v2.1.0".to_string()));
+        package.commit = Some(FULL.to_string());
+        assert_eq!(package.git_ref(), GitRef::Commit(FULL.to_string()));
+        package.branch = Some("main".to_string());
+        assert_eq!(package.git_ref(), GitRef::Branch("main".to_string()));
+        package.tag = Some("release".to_string());
+        assert_eq!(package.git_ref(), GitRef::Tag("release".to_string()));
+    }
+
+    #[test]
+    fn drops_the_pinned_ref_for_a_new_version() {
+        let mut package = mmcv();
+        package.tag = Some("v2.1.0-fixed".to_string());
+        package.branch = Some("main".to_string());
+        package.commit = Some(FULL.to_string());
+        package.set_version("2.2.0");
+
+        assert_eq!(package.version, "2.2.0");
+        assert_eq!(
+            (package.tag, package.branch, package.commit),
+            (None, None, None)
+        );
+        assert_eq!(mmcv().git_ref(), GitRef::Tag("v2.1.0".to_string()));
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Result, bail};
+
+use crate::cache::BuildTarget;
+use crate::pipeline::Pipeline;
+use crate::{App, git_cache, sources, uv_candidate_dirs, uv_is_available};
+
+/// Checks up front that everything an `--offline` run needs is cached
+/// locally, and lists whatever is not instead of failing on the first
+/// network call.
+pub fn check(app: &App) -> Result<()> {
+    let mut missing = Vec::new();
+
+    let uv = if uv_is_available() {
+        Some(PathBuf::from("uv"))
+    } else {
+        uv_candidate_dirs()
+            .into_iter()
+            .map(|dir| dir.join("uv"))
+            .find(|uv| uv.exists())
+    };
+    match &uv {
+        None => missing.push(
+            "uv: not on PATH, in ~/.local/bin or in ~/.cargo/bin; copy a uv binary there"
+                .to_string(),
+        ),
+        Some(uv) if !app.python_bin().exists() && !finds_python(uv, &app.python_version) => {
+            missing.push(format!(
+                "Python {}: no interpreter uv can use for the venv; run `uv python install {}` while online or pass --venv with an existing venv",
+                app.python_version, app.python_version
+            ));
+        }
+        Some(_) => {}
+    }
+
+    // Detected without caching it in the app: torch is installed after this
+    // check, and the run's build records must name that torch. A venv
+    // without torch yet matches no wheel, so every source is required.
+    let target = BuildTarget::detect(app)?;
+    for package in &app.packages {
+        // A purge removes the wheelhouse, so every package gets built.
+        if !app.purge && !Pipeline::new(app, package).needs_build_for(&target)? {
+            continue;
+        }
+        if !package.source.is_local()
+            && sources::cached(package)?.is_none()
+            && !git_cache::has(package)?
+        {
+            missing.push(format!(
+                "{} {}: no cached source for {} in {}",
+                package.name,
+                package.version,
+                package.git_ref,
+                sources::cached_dir(package)?.display()
+            ));
+        }
+        if !package.no_build_isolation {
+            missing.push(format!(
+                "{}: builds with build isolation, which needs the package index (set no-build-isolation = true)",
+                package.name
+            ));
+        }
+    }
+
+    if missing.is_empty() {
+        app.plan("every package has a cached wheel or source");
+        return Ok(());
+    }
+    bail!(
+        "--offline needs local copies of what is missing below (run once without --offline to cache them):\n{}",
+        missing
+            .iter()
+            .map(|line| format!("  - {line}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn finds_python(uv: &Path, version: &str) -> bool {
+    Command::new(uv)
+        .args(["python", "find", version])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
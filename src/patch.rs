@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use console::style;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use crate::manifest::PatchSpec;
+use crate::{cache, torch_load};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchStatus {
+    Applied,
+    AlreadyApplied,
+    NotApplicable,
+    /// A catalog fix with nothing to change: the code never needed it, or
+    /// upstream fixed it. Unlike a declared patch, never a strict failure.
+    NotNeeded,
+    Failed,
+}
+
+impl PatchStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PatchStatus::Applied => "applied",
+            PatchStatus::AlreadyApplied => "already applied",
+            PatchStatus::NotApplicable => "not applicable",
+            PatchStatus::NotNeeded => "not needed",
+            PatchStatus::Failed => "failed",
+        }
+    }
+
+    /// Whether the tree ends up with the patch in place.
+    pub fn is_in_place(self) -> bool {
+        matches!(self, PatchStatus::Applied | PatchStatus::AlreadyApplied)
+    }
+}
+
+/// What happened to one patch (a manifest patch or a diff) in one package.
+#[derive(Debug, Clone)]
+pub struct PatchOutcome {
+    pub patch: String,
+    pub status: PatchStatus,
+    pub detail: String,
+}
+
+impl PatchOutcome {
+    pub fn new(status: PatchStatus, detail: impl Into<String>) -> Self {
+        Self {
+            patch: String::new(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn named(mut self, patch: impl Into<String>) -> Self {
+        self.patch = patch.into();
+        self
+    }
+
+    /// One line, unless the detail spans several (a failing hunk), which are
+    /// then indented below it.
+    pub fn summary(&self) -> String {
+        let status = self.status.as_str();
+        if self.detail.is_empty() {
+            return format!("{}: {status}", self.patch);
+        }
+        match self.detail.split_once('\n') {
+            None => format!("{}: {status} ({})", self.patch, self.detail),
+            Some((first, rest)) => {
+                let rest: Vec<String> = rest.lines().map(|line| format!("      {line}")).collect();
+                format!("{}: {status}: {first}\n{}", self.patch, rest.join("\n"))
+            }
+        }
+    }
+}
+
+pub fn apply(source_dir: &Path, patch: &PatchSpec, version: &str) -> Result<PatchOutcome> {
+    let path = source_dir.join(patch.path());
+    if !path.exists() {
+        return Ok(PatchOutcome::new(
+            PatchStatus::Failed,
+            format!("{} does not exist", patch.path()),
+        ));
+    }
+    match patch {
+        PatchSpec::TorchLoadWeightsOnly { .. } => patch_torch_load_calls(&path),
+        PatchSpec::StaticVersion { .. } => patch_static_version(&path, version),
+    }
+}
+
+/// Prints the per-package patch summary after its build step.
+pub fn print_summary(package: &str, outcomes: &[PatchOutcome]) {
+    if outcomes.is_empty() {
+        return;
+    }
+
+    let counts: Vec<String> = [
+        PatchStatus::Applied,
+        PatchStatus::AlreadyApplied,
+        PatchStatus::NotApplicable,
+        PatchStatus::NotNeeded,
+        PatchStatus::Failed,
+    ]
+    .into_iter()
+    .filter_map(|status| {
+        let count = outcomes
+            .iter()
+            .filter(|outcome| outcome.status == status)
+            .count();
+        (count > 0).then(|| format!("{count} {}", status.as_str()))
+    })
+    .collect();
+    println!(
+        "  {} {}",
+        style(format!("{package} patches:")).cyan(),
+        counts.join(", ")
+    );
+
+    for outcome in outcomes {
+        let (marker, summary) = match outcome.status {
+            PatchStatus::Applied => (style("✔").green(), style(outcome.summary())),
+            PatchStatus::AlreadyApplied => (style("✔").green(), style(outcome.summary()).dim()),
+            PatchStatus::NotApplicable => (
+                style("⚠").yellow().bold(),
+                style(outcome.summary()).yellow(),
+            ),
+            PatchStatus::NotNeeded => (style("–").dim(), style(outcome.summary()).dim()),
+            PatchStatus::Failed => (style("✘").red().bold(), style(outcome.summary()).red()),
+        };
+        println!("    {marker} {summary}");
+    }
+}
+
+/// The files a set of patches may touch, read before they run so the changes
+/// can be shown as unified diffs afterwards.
+pub struct Snapshot {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, Option<String>>,
+}
+
+impl Snapshot {
+    /// `paths` are relative to `root`; directories stand for the `.py` files
+    /// below them and missing paths for files a patch may create.
+    pub fn take<'p>(root: &Path, paths: impl IntoIterator<Item = &'p str>) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for path in paths {
+            let full = root.join(path);
+            if !full.exists() {
+                files.insert(PathBuf::from(path), None);
+                continue;
+            }
+            for file in python_files(&full)? {
+                let relative = file.strip_prefix(root).unwrap_or(&file).to_path_buf();
+                files.insert(relative, Some(read_lossy(&file)?));
+            }
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+            files,
+        })
+    }
+
+    /// A unified diff for every snapshotted file that changed since.
+    pub fn changes(&self) -> Result<Vec<String>> {
+        let mut changes = Vec::new();
+        for (path, before) in &self.files {
+            let full = self.root.join(path);
+            let after = if full.is_file() {
+                Some(read_lossy(&full)?)
+            } else {
+                None
+            };
+            if &after == before {
+                continue;
+            }
+
+            let label = path.display();
+            let old_header = match before {
+                Some(_) => format!("a/{label}"),
+                None => "/dev/null".to_string(),
+            };
+            let new_header = match after {
+                Some(_) => format!("b/{label}"),
+                None => "/dev/null".to_string(),
+            };
+            let old = before.as_deref().unwrap_or_default();
+            let new = after.as_deref().unwrap_or_default();
+            changes.push(
+                TextDiff::from_lines(old, new)
+                    .unified_diff()
+                    .header(&old_header, &new_header)
+                    .to_string(),
+            );
+        }
+        Ok(changes)
+    }
+}
+
+fn read_lossy(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("failed reading {}", path.display()))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Prints unified diffs with the usual colors: headers bold, hunk ranges cyan,
+/// removed lines red and added lines green.
+pub fn print_changes(changes: &[String]) {
+    for change in changes {
+        for line in change.lines() {
+            let styled = if line.starts_with("---") || line.starts_with("+++") {
+                style(line).bold()
+            } else if line.starts_with("@@") {
+                style(line).cyan()
+            } else if line.starts_with('-') {
+                style(line).red()
+            } else if line.starts_with('+') {
+                style(line).green()
+            } else {
+                style(line)
+            };
+            println!("{styled}");
+        }
+    }
+}
+
+/// Stamps `version` into a `setup.py` by replacing its `get_version()`, or
+/// into a version module (`mmcv/version.py`) by rewriting its top-level
+/// `__version__ = '...'`.
+fn patch_static_version(path: &Path, version: &str) -> Result<PatchOutcome> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed reading {}", path.display()))?;
+    let mut lines: Vec<String> = content.lines().map(ToString::to_string).collect();
+
+    let Some(index) = lines
+        .iter()
+        .position(|line| line.trim_end() == "def get_version():")
+    else {
+        return stamp_version_assignment(path, lines, version);
+    };
+
+    let stamped = format!("    return '{version}'");
+    if lines.get(index + 1) == Some(&stamped) {
+        return Ok(PatchOutcome::new(PatchStatus::AlreadyApplied, ""));
+    }
+
+    if lines.len() < index + 4 {
+        return Ok(PatchOutcome::new(
+            PatchStatus::Failed,
+            "get_version() is shorter than the expected four lines",
+        ));
+    }
+
+    lines.splice(
+        index..index + 4,
+        ["def get_version():".to_string(), stamped],
+    );
+
+    let mut rewritten = lines.join("\n");
+    rewritten.push('\n');
+    fs::write(path, rewritten).with_context(|| format!("failed writing {}", path.display()))?;
+    Ok(PatchOutcome::new(
+        PatchStatus::Applied,
+        format!("version {version}"),
+    ))
+}
+
+fn stamp_version_assignment(
+    path: &Path,
+    mut lines: Vec<String>,
+    version: &str,
+) -> Result<PatchOutcome> {
+    let Some(index) = lines.iter().position(|line| {
+        line.strip_prefix("__version__")
+            .is_some_and(|rest| rest.trim_start().starts_with('='))
+    }) else {
+        return Ok(PatchOutcome::new(
+            PatchStatus::NotApplicable,
+            "no `def get_version():` or `__version__ = ...`",
+        ));
+    };
+
+    let stamped = format!("__version__ = '{version}'");
+    if lines[index].trim_end() == stamped {
+        return Ok(PatchOutcome::new(PatchStatus::AlreadyApplied, ""));
+    }
+    lines[index] = stamped;
+
+    let mut rewritten = lines.join("\n");
+    rewritten.push('\n');
+    fs::write(path, rewritten).with_context(|| format!("failed writing {}", path.display()))?;
+    Ok(PatchOutcome::new(
+        PatchStatus::Applied,
+        format!("version {version}"),
+    ))
+}
+
+fn patch_torch_load_calls(path: &Path) -> Result<PatchOutcome> {
+    let files = python_files(path)?;
+    let mut patched = 0usize;
+    let mut patched_files = 0usize;
+    let mut already_set = 0usize;
+    let mut unbalanced = Vec::new();
+
+    for file in &files {
+        let content = fs::read_to_string(file)
+            .with_context(|| format!("failed reading {}", file.display()))?;
+        let rewrite = torch_load::rewrite(&content);
+        if rewrite.patched > 0 {
+            fs::write(file, &rewrite.content)
+                .with_context(|| format!("failed writing {}", file.display()))?;
+            patched += rewrite.patched;
+            patched_files += 1;
+        }
+        already_set += rewrite.already_set;
+        if rewrite.unbalanced > 0 {
+            unbalanced.push(file.display().to_string());
+        }
+    }
+
+    let in_files = if path.is_dir() {
+        format!(" in {patched_files} file(s)")
+    } else {
+        String::new()
+    };
+    Ok(if !unbalanced.is_empty() {
+        PatchOutcome::new(
+            PatchStatus::Failed,
+            format!(
+                "unbalanced torch.load( parentheses in {}; {patched} call(s) patched",
+                unbalanced.join(", ")
+            ),
+        )
+    } else if patched > 0 {
+        PatchOutcome::new(
+            PatchStatus::Applied,
+            format!("{patched} torch.load( call(s){in_files}"),
+        )
+    } else if already_set > 0 {
+        PatchOutcome::new(
+            PatchStatus::AlreadyApplied,
+            "every torch.load( call sets weights_only",
+        )
+    } else {
+        PatchOutcome::new(PatchStatus::NotApplicable, "no torch.load( calls")
+    })
+}
+
+/// `path` itself if it is a file, otherwise every `.py` file below it, skipping
+/// hidden directories and `__pycache__`.
+pub fn python_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("failed to list {}", dir.display()))?
+        {
+            let entry = entry.with_context(|| format!("failed to list {}", dir.display()))?;
+            let entry_path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if entry_path.is_dir() {
+                if !name.starts_with('.') && name != "__pycache__" {
+                    pending.push(entry_path);
+                }
+            } else if name.ends_with(".py") {
+                files.push(entry_path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// sha256 of a patched file, or of every `.py` file under a patched directory.
+pub fn content_sha256(path: &Path) -> Result<String> {
+    if !path.is_dir() {
+        return cache::file_sha256(path);
+    }
+    let mut hasher = Sha256::new();
+    for file in python_files(path)? {
+        let relative = file.strip_prefix(path).unwrap_or(&file);
+        hasher.update(format!(
+            "{} {}\n",
+            cache::file_sha256(&file)?,
+            relative.display()
+        ));
+    }
+    Ok(cache::hex(&hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(name: &str, content: &str) -> (PatchStatus, String) {
+        let path = std::env::temp_dir().join(format!(
+            "mmaction-install-stamp-{name}-{}.py",
+            std::process::id()
+        ));
+        fs::write(&path, content).unwrap();
+        let status = patch_static_version(&path, "2.1.0+mmai.abc")
+            .unwrap()
+            .status;
+        let stamped = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (status, stamped)
+    }
+
+    #[test]
+    fn stamps_get_version_in_setup_py() {
+        let setup = "def get_version():\n    with open(version_file) as f:\n        exec(compile(f.read(), version_file, 'exec'))\n    return locals()['__version__']\n\nsetup()\n";
+        let (status, stamped) = stamp("setup", setup);
+        assert_eq!(status, PatchStatus::Applied);
+        assert_eq!(
+            stamped,
+            "def get_version():\n    return '2.1.0+mmai.abc'\n\nsetup()\n"
+        );
+        assert_eq!(
+            stamp("setup-again", &stamped).0,
+            PatchStatus::AlreadyApplied
+        );
+    }
+
+    #[test]
+    fn stamps_the_version_module() {
+        let module = "# Copyright\n__version__ = '2.1.0'\n\n\ndef parse_version_info(version_str):\n    __version__ = 'shadowed'\n";
+        let (status, stamped) = stamp("module", module);
+        assert_eq!(status, PatchStatus::Applied);
+        assert!(stamped.starts_with("# Copyright\n__version__ = '2.1.0+mmai.abc'\n"));
+        assert!(stamped.contains("    __version__ = 'shadowed'"));
+        assert_eq!(
+            stamp("module-again", &stamped).0,
+            PatchStatus::AlreadyApplied
+        );
+        assert_eq!(
+            stamp("none", "VERSION = '1'\n").0,
+            PatchStatus::NotApplicable
+        );
+    }
+}
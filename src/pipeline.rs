@@ -0,0 +1,847 @@
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+use anyhow::{Context, Result, bail};
+use console::style;
+use glob::glob;
+use sha2::{Digest, Sha256};
+
+use crate::cache::{self, AppliedPatch, BuildRecord, BuildTarget, CacheKey, Lookup, SourceInfo};
+use crate::diff::{self, DiffPatch};
+use crate::fixes::{Catalog, Fix};
+use crate::git_cache;
+use crate::manifest::{GitRef, PackageManifest, PatchSpec};
+use crate::patch::{PatchOutcome, PatchStatus, Snapshot};
+use crate::sources::{self, Source, SourceStamp};
+use crate::wheel_store::WheelStore;
+use crate::{
+    App, OutputMode, WHEELHOUSE, doctor, patch, print_warnings, remove_dir_if_exists, run_command,
+};
+
+/// PEP 440 local version label prefix for patched builds.
+const LOCAL_LABEL: &str = "mmai";
+
+/// Inherited variables that change what `pip wheel` produces; recorded in the
+/// build record alongside the variant and manifest settings.
+const BUILD_ENV_VARS: &[&str] = &[
+    "CC",
+    "CXX",
+    "CFLAGS",
+    "CXXFLAGS",
+    "LDFLAGS",
+    "CUDA_HOME",
+    "CUDA_VISIBLE_DEVICES",
+    "TORCH_CUDA_ARCH_LIST",
+    "FORCE_CUDA",
+    "MAX_JOBS",
+    "MMCV_WITH_OPS",
+];
+
+/// A manifest package resolved into everything the build pipeline needs.
+#[derive(Debug, Clone)]
+pub struct PackageSpec {
+    pub name: String,
+    pub version: String,
+    pub git_ref: GitRef,
+    /// The commit the source must be at, when the manifest pins one.
+    pub commit: Option<String>,
+    pub source: Source,
+    pub source_dir: PathBuf,
+    pub patches: Vec<PatchSpec>,
+    pub diffs: Vec<DiffPatch>,
+    pub fixes: Vec<Fix>,
+    pub no_build_isolation: bool,
+    pub build_env: BTreeMap<String, String>,
+    pub pip_args: Vec<String>,
+    /// `source_key`, computed on first use: hashing a local tree is slow and
+    /// the preflight, offline check and build all need it.
+    source_key: OnceCell<String>,
+}
+
+impl PackageSpec {
+    pub fn from_manifest(
+        package: &PackageManifest,
+        catalog: &Catalog,
+        patch_dir: &Path,
+    ) -> Result<Self> {
+        Ok(Self {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            git_ref: package.git_ref(),
+            commit: package.expected_commit(),
+            source: Source::from_manifest(package),
+            source_dir: package.source_dir(),
+            patches: package.patches.clone(),
+            diffs: diff::discover(&package.name, &package.version, patch_dir)?,
+            fixes: catalog.matching(&package.name, &package.version),
+            no_build_isolation: package.build.no_build_isolation,
+            build_env: package.build.env.clone(),
+            pip_args: package.build.pip_args.clone(),
+            source_key: OnceCell::new(),
+        })
+    }
+
+    /// Pins the exact build, local version label included.
+    pub fn requirement(&self) -> String {
+        format!("{}=={}", self.name, self.build_version())
+    }
+
+    /// The version stamped into the wheel. Packages whose code is patched get
+    /// a PEP 440 local label (`0.10.7+mmai.<patch hash>`) so they cannot be
+    /// mistaken for the PyPI release; stamping needs a static-version patch.
+    pub fn build_version(&self) -> String {
+        let stamps_version = self
+            .patches
+            .iter()
+            .any(|patch| matches!(patch, PatchSpec::StaticVersion { .. }));
+        let patches_code = !self.diffs.is_empty()
+            || !self.fixes.is_empty()
+            || self
+                .patches
+                .iter()
+                .any(|patch| !matches!(patch, PatchSpec::StaticVersion { .. }));
+        if stamps_version && patches_code {
+            format!(
+                "{}+{LOCAL_LABEL}.{}",
+                self.version,
+                self.patch_fingerprint()
+            )
+        } else {
+            self.version.clone()
+        }
+    }
+
+    /// Distribution name as it appears in wheel filenames (`-` normalized to `_`).
+    pub fn wheel_name(&self) -> String {
+        self.name.replace('-', "_")
+    }
+
+    /// Wheels of the selected version, whatever they were built against and
+    /// whatever local label they carry.
+    pub fn version_wheels(&self) -> Result<Vec<PathBuf>> {
+        self.version_wheels_in(Path::new(WHEELHOUSE))
+    }
+
+    pub fn version_wheels_in(&self, wheelhouse: &Path) -> Result<Vec<PathBuf>> {
+        let prefix = format!(
+            "{}/{}-{}",
+            wheelhouse.display(),
+            self.wheel_name(),
+            self.version
+        );
+        let mut wheels = glob_wheels(&format!("{prefix}-*.whl"))?;
+        wheels.extend(glob_wheels(&format!("{prefix}+*.whl"))?);
+        Ok(wheels)
+    }
+
+    /// Every wheel of this package in the wheelhouse, regardless of version.
+    pub fn wheelhouse_wheels(&self) -> Result<Vec<PathBuf>> {
+        glob_wheels(&format!("{WHEELHOUSE}/{}-*.whl", self.wheel_name()))
+    }
+
+    /// What the wheel is built from: the repository, local tree or archive,
+    /// and the ref when it is not the version's tag (another tag, a branch,
+    /// a commit or a pinned commit).
+    pub fn source_key(&self) -> Result<String> {
+        if let Some(key) = self.source_key.get() {
+            return Ok(key.clone());
+        }
+        let mut parts = vec![self.source.key()?];
+        if self.git_ref != GitRef::Tag(format!("v{}", self.version)) {
+            parts.push(self.git_ref.to_string());
+        }
+        if let Some(commit) = &self.commit
+            && !matches!(self.git_ref, GitRef::Commit(_))
+        {
+            parts.push(format!("commit {commit}"));
+        }
+        Ok(self.source_key.get_or_init(|| parts.join(", ")).clone())
+    }
+
+    /// Short hash of the patch set, so editing the manifest's patches, a
+    /// diff file or a catalog fix invalidates cached wheels.
+    pub fn patch_fingerprint(&self) -> String {
+        if self.patches.is_empty() && self.diffs.is_empty() && self.fixes.is_empty() {
+            return "none".to_string();
+        }
+        let mut hasher = Sha256::new();
+        for diff in &self.diffs {
+            hasher.update(format!("diff:{}\n", diff.sha256));
+        }
+        for fix in &self.fixes {
+            hasher.update(format!("fix:{}:{}\n", fix.id, fix.sha256()));
+        }
+        for patch in &self.patches {
+            hasher.update(format!("{}:{}\n", patch.kind(), patch.path()));
+        }
+        cache::hex(&hasher.finalize()[..6])
+    }
+}
+
+fn glob_wheels(pattern: &str) -> Result<Vec<PathBuf>> {
+    let entries = glob(pattern).with_context(|| format!("invalid glob pattern: {pattern}"))?;
+    Ok(entries.collect::<std::result::Result<Vec<_>, _>>()?)
+}
+
+/// Clone → patch → wheel → install for a single package.
+pub struct Pipeline<'a> {
+    app: &'a App,
+    spec: &'a PackageSpec,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(app: &'a App, spec: &'a PackageSpec) -> Self {
+        Self { app, spec }
+    }
+
+    pub fn run(&self, outcomes: &mut Vec<PatchOutcome>) -> Result<()> {
+        self.build(outcomes)?;
+        self.stage("install", || self.install())
+    }
+
+    /// Ensures a wheel for the package is in the wheelhouse, building it on a
+    /// cache miss. Patch outcomes are collected for the caller's summary.
+    pub fn build(&self, outcomes: &mut Vec<PatchOutcome>) -> Result<()> {
+        let key = self.cache_key()?;
+        let stale = match self.lookup(&key)? {
+            Lookup::Hit(wheel) => {
+                self.note(&format!("using cached wheel {}", wheel.display()));
+                return Ok(());
+            }
+            Lookup::Miss { reason, stale } => {
+                self.note(&format!("rebuilding: {reason}"));
+                stale
+            }
+        };
+
+        // Another project may have built this wheel already, or be building
+        // it now; the lock makes this install wait for it and take it. A
+        // store this user cannot lock is left out of the build altogether.
+        let (store, _store_lock) = match &self.app.wheel_store {
+            Some(store) => match store.lock(self.app, self.spec, &key) {
+                Ok(lock) => (Some(store), lock),
+                Err(error) => {
+                    print_warnings(&[format!(
+                        "wheel store unavailable, building {} without it: {error:#}",
+                        self.spec.name
+                    )]);
+                    (None, None)
+                }
+            },
+            None => (None, None),
+        };
+        if let Some(store) = store
+            && self.stage("check wheel store", || self.pull(store, &key, &stale))?
+        {
+            return Ok(());
+        }
+
+        let commit = self.stage("fetch source", || self.fetch_source())?;
+        // In debug mode, show and keep what the patches changed.
+        let snapshot = if self.app.debug && !self.app.dry_run {
+            Some(self.snapshot()?)
+        } else {
+            None
+        };
+        let patches = self.stage("apply patches", || self.apply_patches(outcomes))?;
+        if let Some(snapshot) = snapshot {
+            self.stage("diff patched sources", || self.log_changes(&snapshot))?;
+        }
+        // A wheel built past a failed patch would be cached, and labelled, as
+        // carrying the whole patch set; only an inapplicable patch, which
+        // leaves nothing to change, may be let through. Catalog fixes are
+        // matched by version range rather than declared, so one that finds
+        // nothing is never an error.
+        let missing: Vec<String> = outcomes
+            .iter()
+            .filter(|outcome| match outcome.status {
+                PatchStatus::Failed => true,
+                PatchStatus::NotApplicable => self.app.strict_patches,
+                PatchStatus::Applied | PatchStatus::AlreadyApplied | PatchStatus::NotNeeded => {
+                    false
+                }
+            })
+            .map(|outcome| format!("  - {}", outcome.summary()))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "{}: {} patch(es) did not apply{}:\n{}",
+                self.spec.name,
+                missing.len(),
+                if self.app.strict_patches {
+                    " (--strict-patches)"
+                } else {
+                    ""
+                },
+                missing.join("\n")
+            );
+        }
+        self.stage("build wheel", || {
+            self.build_wheel(&key, &stale, commit, patches, store)
+        })
+    }
+
+    /// Applies the patches to a checkout of the tag, outside the package's
+    /// source directory, and returns what they changed. Never touches the
+    /// wheelhouse or the venv; the checkout is reset afterwards so the next
+    /// check can reuse it.
+    pub fn check(&self, outcomes: &mut Vec<PatchOutcome>) -> Result<Vec<String>> {
+        self.stage("fetch source", || self.checkout())?;
+        if self.app.dry_run {
+            self.stage("apply patches", || self.apply_patches(outcomes))?;
+            return Ok(Vec::new());
+        }
+
+        let snapshot = self.snapshot()?;
+        let applied = self.stage("apply patches", || self.apply_patches(outcomes));
+        let changes = applied.and_then(|_| snapshot.changes());
+        self.stage("reset checkout", || self.reset_checkout())?;
+        changes
+    }
+
+    pub fn needs_build(&self) -> Result<bool> {
+        self.needs_build_for(self.app.build_target()?)
+    }
+
+    /// Like `needs_build`, against a target detected by the caller. Checks
+    /// that run before torch is installed use this, so the run's own target
+    /// is only detected once the venv has its final torch.
+    pub fn needs_build_for(&self, target: &BuildTarget) -> Result<bool> {
+        let key = CacheKey::new(target, self.spec)?;
+        if let Lookup::Hit(_) = self.lookup(&key)? {
+            return Ok(false);
+        }
+        Ok(match &self.app.wheel_store {
+            Some(store) => store.find(self.spec, &key)?.is_none(),
+            None => true,
+        })
+    }
+
+    fn cache_key(&self) -> Result<CacheKey> {
+        CacheKey::new(self.app.build_target()?, self.spec)
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Result<Lookup> {
+        if self.app.dry_run && self.app.purge {
+            return Ok(Lookup::Miss {
+                reason: "--purge removes the wheelhouse".to_string(),
+                stale: Vec::new(),
+            });
+        }
+        cache::lookup(self.spec, key)
+    }
+
+    /// Takes the wheel from the shared wheel store instead of building it.
+    /// False when the store has none for this build.
+    fn pull(&self, store: &WheelStore, key: &CacheKey, stale: &[PathBuf]) -> Result<bool> {
+        let Some(record) = store.find(self.spec, key)? else {
+            self.note(&format!("no matching wheel in {}", store.root().display()));
+            return Ok(false);
+        };
+        self.remove_stale(stale)?;
+        if self.app.dry_run {
+            self.app.plan(&format!(
+                "would copy {} from the wheel store {}",
+                record.wheel,
+                store.root().display()
+            ));
+            return Ok(true);
+        }
+        if !store.pull(&record)? {
+            self.note(&format!(
+                "{} in the wheel store does not match its sha256; rebuilding",
+                record.wheel
+            ));
+            return Ok(false);
+        }
+        self.note(&format!("using {} from the wheel store", record.wheel));
+        Ok(true)
+    }
+
+    fn stage<T>(&self, stage: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.note(stage);
+        f().with_context(|| format!("{}: {stage} failed", self.spec.name))
+    }
+
+    fn note(&self, message: &str) {
+        if self.app.verbose() {
+            println!(
+                "  {} {}",
+                style(format!("{}:", self.spec.name)).cyan(),
+                style(message).dim()
+            );
+        }
+    }
+
+    /// Fetches the source into the source cache (offline: takes the cached
+    /// tree if there is one, and otherwise checks the ref out of the git
+    /// cache), copies it to the source directory and returns its commit.
+    fn fetch_source(&self) -> Result<Option<String>> {
+        // Keeps other installs from replacing the cached tree while it is
+        // read and copied.
+        let _lock = sources::lock(self.app, self.spec)?;
+        let offline_tree = self.app.offline
+            && !self.spec.source.is_local()
+            && (sources::cached(self.spec)?.is_some() || !git_cache::has(self.spec)?);
+        let stamp = if offline_tree {
+            Some(self.cached_source()?)
+        } else {
+            sources::fetch(self.app, self.spec)?
+        };
+        if let Some(stamp) = &stamp
+            && stamp.fetched_from != stamp.repository
+        {
+            self.note(&format!("fetched from {}", stamp.fetched_from));
+        }
+        self.copy_cached_source()?;
+        Ok(stamp.and_then(|stamp| stamp.commit))
+    }
+
+    fn cached_source(&self) -> Result<SourceStamp> {
+        let stamp = sources::cached(self.spec)?.with_context(|| {
+            format!(
+                "no cached source for {} {} (--offline)",
+                self.spec.name, self.spec.git_ref
+            )
+        })?;
+        sources::verify_commit(self.spec, stamp.commit.as_deref())?;
+        Ok(stamp)
+    }
+
+    fn copy_cached_source(&self) -> Result<()> {
+        let cached = sources::cached_dir(self.spec)?;
+        let source_dir = &self.spec.source_dir;
+        if self.app.dry_run {
+            self.app.plan(&format!(
+                "would copy {} to {}",
+                cached.display(),
+                source_dir.display()
+            ));
+            return Ok(());
+        }
+        remove_dir_if_exists(source_dir)?;
+        sources::copy_tree(&cached, source_dir)
+    }
+
+    /// The files the diffs and manifest patches may touch, before they run.
+    fn snapshot(&self) -> Result<Snapshot> {
+        let paths = self
+            .spec
+            .diffs
+            .iter()
+            .flat_map(DiffPatch::paths)
+            .chain(
+                self.spec
+                    .fixes
+                    .iter()
+                    .flat_map(|fix| fix.paths.iter().map(String::as_str)),
+            )
+            .chain(self.spec.patches.iter().map(PatchSpec::path));
+        Snapshot::take(&self.spec.source_dir, paths)
+    }
+
+    /// Prints the patched files' diffs and saves them as `<package>.diff` in
+    /// the run's log directory.
+    fn log_changes(&self, snapshot: &Snapshot) -> Result<()> {
+        let changes = snapshot.changes()?;
+        if changes.is_empty() {
+            self.note("the patches changed no files");
+            return Ok(());
+        }
+        patch::print_changes(&changes);
+
+        let log_dir = &self.app.run_log_dir;
+        fs::create_dir_all(log_dir)
+            .with_context(|| format!("failed to create {}", log_dir.display()))?;
+        let path = log_dir.join(format!("{}.diff", self.spec.name));
+        fs::write(&path, changes.concat())
+            .with_context(|| format!("failed writing {}", path.display()))?;
+        self.note(&format!("saved the patch diff to {}", path.display()));
+        Ok(())
+    }
+
+    /// A fresh shallow clone of the tag into the source directory, `.git`
+    /// included.
+    fn clone_source(&self, urls: &[String]) -> Result<()> {
+        let spec = self.spec;
+        if self.app.dry_run {
+            if spec.source_dir.exists() {
+                self.app
+                    .plan(&format!("would remove {}", spec.source_dir.display()));
+            }
+        } else {
+            remove_dir_if_exists(&spec.source_dir)?;
+        }
+        sources::clone(self.app, spec, urls, &spec.source_dir)?;
+        if self.app.dry_run {
+            return Ok(());
+        }
+        sources::verify_commit(spec, sources::head_commit(&spec.source_dir).as_deref())
+    }
+
+    /// Like `fetch_source`, but keeps `.git` so an earlier checkout of the
+    /// same ref is reused after resetting it. For local sources, and offline
+    /// when the git cache lacks the ref, the tree is copied instead, every
+    /// time.
+    fn checkout(&self) -> Result<()> {
+        let Source::Git(urls) = &self.spec.source else {
+            return self.fetch_source().map(drop);
+        };
+        if self.app.offline && !git_cache::has(self.spec)? {
+            self.cached_source()?;
+            return self.copy_cached_source();
+        }
+        if !self.spec.source_dir.join(".git").is_dir() {
+            return self.clone_source(urls);
+        }
+        self.note(&format!("reusing {}", self.spec.source_dir.display()));
+        self.reset_checkout()?;
+        sources::verify_commit(
+            self.spec,
+            sources::head_commit(&self.spec.source_dir).as_deref(),
+        )
+    }
+
+    fn reset_checkout(&self) -> Result<()> {
+        // Copied trees have no history to reset to; the next check copies
+        // them again.
+        if !self.spec.source_dir.join(".git").is_dir() {
+            return Ok(());
+        }
+        for args in [&["reset", "--hard", "-q"][..], &["clean", "-fdxq"]] {
+            let mut git = Command::new("git");
+            git.arg("-C").arg(&self.spec.source_dir).args(args);
+            run_command(
+                self.app,
+                &format!("reset {} checkout", self.spec.name),
+                git,
+                OutputMode::Quiet,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn apply_patches(&self, outcomes: &mut Vec<PatchOutcome>) -> Result<Vec<AppliedPatch>> {
+        let build_version = self.spec.build_version();
+        if build_version != self.spec.version {
+            self.note(&format!("stamping version {build_version}"));
+        }
+
+        // Diffs are written against the pristine upstream tree, so they go
+        // first; then the catalog fixes, then the manifest patches.
+        let mut applied = Vec::new();
+        for diff in &self.spec.diffs {
+            if self.app.dry_run {
+                self.app.plan(&format!(
+                    "would apply {} to {}",
+                    diff.name,
+                    diff.paths().join(", ")
+                ));
+                continue;
+            }
+            let outcome = diff.apply(&self.spec.source_dir)?.named(&diff.name);
+            if outcome.status.is_in_place() {
+                applied.push(AppliedPatch {
+                    kind: "diff".to_string(),
+                    path: diff.name.clone(),
+                    sha256: diff.sha256.clone(),
+                });
+            }
+            outcomes.push(outcome);
+        }
+        for fix in &self.spec.fixes {
+            self.note(&format!("fix {}: {}", fix.id, fix.reason));
+            if self.app.dry_run {
+                self.app.plan(&format!(
+                    "would apply fix {} to {}",
+                    fix.id,
+                    fix.paths.join(", ")
+                ));
+                continue;
+            }
+            let outcome = fix
+                .apply(&self.spec.source_dir)?
+                .named(format!("fix {}", fix.id));
+            if outcome.status.is_in_place() {
+                applied.push(AppliedPatch {
+                    kind: "fix".to_string(),
+                    path: fix.id.clone(),
+                    sha256: fix.sha256(),
+                });
+            }
+            outcomes.push(outcome);
+        }
+        for patch in &self.spec.patches {
+            let path = self.spec.source_dir.join(patch.path());
+            if self.app.dry_run {
+                self.app.plan(&format!(
+                    "would apply {} to {}",
+                    patch.kind(),
+                    path.display()
+                ));
+                continue;
+            }
+            let outcome = patch::apply(&self.spec.source_dir, patch, &build_version)?
+                .named(format!("{} {}", patch.kind(), patch.path()));
+            if outcome.status.is_in_place() {
+                applied.push(AppliedPatch {
+                    kind: patch.kind().to_string(),
+                    path: patch.path().to_string(),
+                    sha256: patch::content_sha256(&path)?,
+                });
+            }
+            outcomes.push(outcome);
+        }
+        Ok(applied)
+    }
+
+    /// Environment variables that affect the build: the relevant ones
+    /// inherited from the shell, then the build variant's, then the manifest's.
+    fn build_env(&self) -> BTreeMap<String, String> {
+        let mut env: BTreeMap<String, String> = BUILD_ENV_VARS
+            .iter()
+            .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+            .collect();
+        env.extend(
+            self.app
+                .build_variant
+                .build_env()
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        env.extend(self.spec.build_env.clone());
+        env
+    }
+
+    fn build_wheel(
+        &self,
+        key: &CacheKey,
+        stale: &[PathBuf],
+        commit: Option<String>,
+        patches: Vec<AppliedPatch>,
+        store: Option<&WheelStore>,
+    ) -> Result<()> {
+        let spec = self.spec;
+        let build_env = self.build_env();
+        // Drop rejected wheels first so a failed build cannot leave one
+        // behind for the install step to pick up.
+        self.remove_stale(stale)?;
+
+        let mut wheel = Command::new(self.app.python_bin());
+        wheel
+            .args(["-m", "pip", "wheel", "-v"])
+            .arg(Path::new(".").join(&spec.source_dir))
+            .arg("--no-deps");
+        if spec.no_build_isolation {
+            wheel.arg("--no-build-isolation");
+        }
+        wheel
+            .args(&spec.pip_args)
+            .args(["--wheel-dir", WHEELHOUSE])
+            .envs(&build_env);
+        let built_at = cache::utc_timestamp();
+        let started = Instant::now();
+        run_command(
+            self.app,
+            &format!("build {} wheel", spec.name),
+            wheel,
+            OutputMode::Quiet,
+        )?;
+
+        if self.app.dry_run {
+            if let Some(store) = store {
+                self.app.plan(&format!(
+                    "would publish the wheel to the wheel store {}",
+                    store.root().display()
+                ));
+            }
+            return Ok(());
+        }
+        let built = self.newest_wheel()?.with_context(|| {
+            format!(
+                "pip reported success but no {} wheel is in {WHEELHOUSE}",
+                spec.name
+            )
+        })?;
+
+        let record = BuildRecord {
+            package: spec.name.clone(),
+            version: spec.build_version(),
+            wheel: cache::file_name(&built),
+            sha256: cache::file_sha256(&built)?,
+            cache_key: key.clone(),
+            source: SourceInfo {
+                repository: spec.source.origin(),
+                git_ref: spec.git_ref.name().to_string(),
+                commit,
+            },
+            patches,
+            compiler: doctor::find_compiler("CXX", doctor::CXX_COMPILERS)
+                .map(|(program, version)| format!("{program}: {version}")),
+            build_env,
+            built_at,
+            build_seconds: (started.elapsed().as_secs_f64() * 10.0).round() / 10.0,
+        };
+        let record_path = record.write()?;
+        self.note(&format!("recorded build in {}", record_path.display()));
+
+        if let Some(store) = store {
+            // The wheel is built and recorded; a store that cannot take it
+            // only costs other projects a build.
+            match store.publish(spec, &record, &built) {
+                Ok(()) => self.note(&format!(
+                    "published {} to {}",
+                    record.wheel,
+                    store.root().display()
+                )),
+                Err(error) => print_warnings(&[format!(
+                    "could not publish {} to the wheel store: {error:#}",
+                    record.wheel
+                )]),
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_stale(&self, stale: &[PathBuf]) -> Result<()> {
+        for wheel in stale {
+            if self.app.dry_run {
+                self.app
+                    .plan(&format!("would remove stale wheel {}", wheel.display()));
+            } else {
+                cache::remove_wheel(wheel)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn newest_wheel(&self) -> Result<Option<PathBuf>> {
+        let mut newest = None;
+        for wheel in self.spec.version_wheels()? {
+            let modified = fs::metadata(&wheel)
+                .and_then(|metadata| metadata.modified())
+                .with_context(|| format!("failed to stat {}", wheel.display()))?;
+            if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+                newest = Some((modified, wheel));
+            }
+        }
+        Ok(newest.map(|(_, wheel)| wheel))
+    }
+
+    fn install(&self) -> Result<()> {
+        let mut install = Command::new("uv");
+        install
+            .arg("pip")
+            .arg("install")
+            .arg("-v")
+            .arg("--python")
+            .arg(self.app.python_bin())
+            .arg("--no-deps")
+            .arg("--no-index")
+            .arg("--find-links")
+            .arg(WHEELHOUSE)
+            .arg(self.spec.requirement());
+        run_command(
+            self.app,
+            &format!("install {}", self.spec.name),
+            install,
+            OutputMode::Quiet,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest;
+
+    /// The bundled mmengine, with `patches` instead of the manifest's and
+    /// without its bundled diff.
+    fn mmengine(patches: &[PatchSpec]) -> PackageSpec {
+        let manifest = Manifest::bundled().unwrap();
+        let package = manifest
+            .packages
+            .iter()
+            .find(|package| package.name == "mmengine")
+            .unwrap();
+        let mut spec =
+            PackageSpec::from_manifest(package, &Catalog::default(), Path::new("no-patches"))
+                .unwrap();
+        spec.patches = patches.to_vec();
+        spec.diffs.clear();
+        spec
+    }
+
+    fn stamp() -> PatchSpec {
+        PatchSpec::StaticVersion {
+            path: "setup.py".to_string(),
+        }
+    }
+
+    fn torch_load() -> PatchSpec {
+        PatchSpec::TorchLoadWeightsOnly {
+            path: "mmengine".to_string(),
+        }
+    }
+
+    #[test]
+    fn labels_stamped_builds_with_patched_code() {
+        let spec = mmengine(&[stamp(), torch_load()]);
+        assert_eq!(
+            spec.build_version(),
+            format!("0.10.7+mmai.{}", spec.patch_fingerprint())
+        );
+        assert_eq!(spec.patch_fingerprint().len(), 12);
+        assert_eq!(
+            spec.requirement(),
+            format!("mmengine=={}", spec.build_version())
+        );
+    }
+
+    #[test]
+    fn keeps_the_release_version_without_patched_code() {
+        assert_eq!(mmengine(&[stamp()]).build_version(), "0.10.7");
+        assert_eq!(mmengine(&[]).build_version(), "0.10.7");
+    }
+
+    #[test]
+    fn keeps_the_release_version_when_it_cannot_be_stamped() {
+        assert_eq!(mmengine(&[torch_load()]).build_version(), "0.10.7");
+    }
+
+    #[test]
+    fn counts_catalog_fixes_as_patched_code() {
+        let mut spec = mmengine(&[stamp()]);
+        spec.fixes = Catalog::load(Path::new("no-local-catalog"))
+            .unwrap()
+            .fixes
+            .into_iter()
+            .take(1)
+            .collect();
+        assert!(spec.build_version().starts_with("0.10.7+mmai."));
+    }
+
+    #[test]
+    fn computes_the_source_key_once() {
+        let tree = std::env::temp_dir().join(format!(
+            "mmaction-install-pipeline-key-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&tree);
+        fs::create_dir_all(&tree).unwrap();
+        fs::write(tree.join("setup.py"), "version = '0.10.7'\n").unwrap();
+
+        let mut spec = mmengine(&[]);
+        spec.source = Source::Path(tree.clone());
+        let key = spec.source_key().unwrap();
+        assert!(key.starts_with("path "), "{key}");
+        fs::write(tree.join("setup.py"), "version = '0.10.8'\n").unwrap();
+        assert_eq!(spec.source_key().unwrap(), key);
+        assert_ne!(spec.source.key().unwrap(), key);
+        fs::remove_dir_all(&tree).unwrap();
+    }
+}
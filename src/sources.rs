@@ -0,0 +1,563 @@
+use std::fs::{self, File};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::git_cache::Mirror;
+use crate::manifest::{GitRef, PackageManifest, short_commit};
+use crate::pipeline::PackageSpec;
+use crate::{App, OutputMode, cache, lock_exclusive, remove_dir_if_exists, run_command};
+
+const CACHE_DIR_NAME: &str = "mmaction-install";
+const SOURCES_DIR: &str = "sources";
+const STAMP_EXTENSION: &str = "json";
+
+/// Where a package's source tree comes from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Git URLs tried in order: the repository, then its mirrors.
+    Git(Vec<String>),
+    /// A local tree, copied without its `.git`.
+    Path(PathBuf),
+    /// A source archive; a single top-level directory in it is stripped.
+    Tarball(PathBuf),
+}
+
+impl Source {
+    pub fn from_manifest(package: &PackageManifest) -> Self {
+        if let Some(path) = &package.path {
+            Source::Path(PathBuf::from(path))
+        } else if let Some(tarball) = &package.tarball {
+            Source::Tarball(PathBuf::from(tarball))
+        } else {
+            Source::Git(
+                std::iter::once(&package.repository)
+                    .chain(&package.mirrors)
+                    .cloned()
+                    .collect(),
+            )
+        }
+    }
+
+    /// What the source cache and the build records know the source by: the
+    /// repository (not the mirror that served it), or the local path.
+    pub fn origin(&self) -> String {
+        match self {
+            Source::Git(urls) => urls.first().cloned().unwrap_or_default(),
+            Source::Path(path) | Source::Tarball(path) => path.display().to_string(),
+        }
+    }
+
+    /// What the cache key knows the source by: the repository, or a local
+    /// tree or archive with what is in it now, so editing the tree or
+    /// replacing the archive invalidates the wheels built from it.
+    pub fn key(&self) -> Result<String> {
+        Ok(match self {
+            Source::Git(_) => self.origin(),
+            Source::Path(path) if path.is_dir() => format!(
+                "path {} at {}, tree {}",
+                path.display(),
+                head_commit(path).map_or("no commit".to_string(), |commit| short_commit(&commit)
+                    .to_string()),
+                &tree_sha256(path)?[..12]
+            ),
+            Source::Tarball(path) if path.is_file() => format!(
+                "tarball {}, sha256 {}",
+                path.display(),
+                &cache::file_sha256(path)?[..12]
+            ),
+            Source::Path(path) => format!("path {}", path.display()),
+            Source::Tarball(path) => format!("tarball {}", path.display()),
+        })
+    }
+
+    /// A local tree or archive that exists needs no network, so it is read
+    /// even in offline mode.
+    pub fn is_local(&self) -> bool {
+        match self {
+            Source::Git(_) => false,
+            Source::Path(path) => path.is_dir(),
+            Source::Tarball(path) => path.is_file(),
+        }
+    }
+}
+
+/// Written next to a cached source tree once it is complete; a tree without
+/// its stamp is a fetch that did not finish.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceStamp {
+    pub repository: String,
+    /// The mirror or path the tree was actually read from.
+    #[serde(default)]
+    pub fetched_from: String,
+    #[serde(rename = "ref", alias = "tag")]
+    pub git_ref: String,
+    pub commit: Option<String>,
+    pub fetched_at: String,
+}
+
+/// `$XDG_CACHE_HOME/mmaction-install`, or `~/.cache/mmaction-install`.
+pub fn cache_root() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir).join(CACHE_DIR_NAME));
+    }
+    let home = std::env::var_os("HOME")
+        .context("neither XDG_CACHE_HOME nor HOME is set; cannot locate the source cache")?;
+    Ok(PathBuf::from(home).join(".cache").join(CACHE_DIR_NAME))
+}
+
+/// The pristine tree of the package's ref in the source cache.
+pub fn cached_dir(spec: &PackageSpec) -> Result<PathBuf> {
+    Ok(cache_root()?.join(SOURCES_DIR).join(format!(
+        "{}-{}",
+        spec.name,
+        spec.git_ref.name().replace('/', "_")
+    )))
+}
+
+/// `<dir>.json`; tags contain dots, so this appends rather than replaces.
+fn stamp_path(dir: &Path) -> PathBuf {
+    let mut path = dir.as_os_str().to_owned();
+    path.push(format!(".{STAMP_EXTENSION}"));
+    PathBuf::from(path)
+}
+
+/// Locks the package's tree in the source cache, which installs in other
+/// projects share, for as long as the file is held. `None` in dry-run mode.
+pub fn lock(app: &App, spec: &PackageSpec) -> Result<Option<File>> {
+    if app.dry_run {
+        return Ok(None);
+    }
+    let mut path = cached_dir(spec)?.into_os_string();
+    path.push(".lock");
+    lock_exclusive(app, Path::new(&path), "the source cache").map(Some)
+}
+
+/// The stamp of a complete cached tree of this source and ref, if any.
+pub fn cached(spec: &PackageSpec) -> Result<Option<SourceStamp>> {
+    let dir = cached_dir(spec)?;
+    let Ok(content) = fs::read_to_string(stamp_path(&dir)) else {
+        return Ok(None);
+    };
+    let Ok(stamp) = serde_json::from_str::<SourceStamp>(&content) else {
+        return Ok(None);
+    };
+    Ok((stamp.repository == spec.source.origin()
+        && stamp.git_ref == spec.git_ref.name()
+        && dir.is_dir())
+    .then_some(stamp))
+}
+
+/// Reads the package's source into the source cache, replacing what was
+/// there, and stamps it: a checkout of the ref from the git cache, a copy of
+/// a local tree or an extracted archive. A tree that is not
+/// at the pinned commit is removed again. `None` in dry-run mode.
+pub fn fetch(app: &App, spec: &PackageSpec) -> Result<Option<SourceStamp>> {
+    let dir = cached_dir(spec)?;
+    let stamp_path = stamp_path(&dir);
+    if app.dry_run {
+        if dir.exists() {
+            app.plan(&format!("would replace {}", dir.display()));
+        }
+    } else {
+        if stamp_path.exists() {
+            fs::remove_file(&stamp_path)
+                .with_context(|| format!("failed to remove {}", stamp_path.display()))?;
+        }
+        remove_dir_if_exists(&dir)?;
+        if let Some(parent) = dir.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+    }
+
+    let (fetched_from, commit) = match &spec.source {
+        Source::Git(urls) => {
+            let url = clone(app, spec, urls, &dir)?;
+            (url, head_commit(&dir))
+        }
+        Source::Path(path) => {
+            if app.dry_run {
+                app.plan(&format!(
+                    "would copy {} to {}",
+                    path.display(),
+                    dir.display()
+                ));
+            } else {
+                if !path.is_dir() {
+                    bail!("source path {} does not exist", path.display());
+                }
+                copy_tree_except(path, &dir, &[".git"])?;
+            }
+            (path.display().to_string(), head_commit(path))
+        }
+        Source::Tarball(archive) => {
+            extract(app, spec, archive, &dir)?;
+            (archive.display().to_string(), None)
+        }
+    };
+    if app.dry_run {
+        if let Some(commit) = &spec.commit {
+            app.plan(&format!(
+                "would verify that the source is at commit {commit}"
+            ));
+        }
+        return Ok(None);
+    }
+    if let Err(error) = verify_commit(spec, commit.as_deref()) {
+        remove_dir_if_exists(&dir)?;
+        return Err(error);
+    }
+
+    let stamp = SourceStamp {
+        repository: spec.source.origin(),
+        fetched_from,
+        git_ref: spec.git_ref.name().to_string(),
+        commit,
+        fetched_at: cache::utc_timestamp(),
+    };
+    remove_dir_if_exists(dir.join(".git"))?;
+    let json = serde_json::to_string_pretty(&stamp).context("failed to serialize source stamp")?;
+    fs::write(&stamp_path, json + "\n")
+        .with_context(|| format!("failed writing {}", stamp_path.display()))?;
+    Ok(Some(stamp))
+}
+
+/// Checks the ref out of the package's git cache into `dir`, `.git`
+/// included, and returns where it came from: the URL the cache was updated
+/// from, or the cache itself when it already had the ref. Branches are
+/// always updated; tags and commits only when the cache lacks them. Offline,
+/// the cache is never updated.
+pub fn clone(app: &App, spec: &PackageSpec, urls: &[String], dir: &Path) -> Result<String> {
+    let mirror = Mirror::for_repository(&spec.source.origin())?;
+    let _lock = if app.dry_run {
+        None
+    } else {
+        Some(mirror.lock(app)?)
+    };
+
+    let cached = mirror.resolve(&spec.git_ref);
+    let fetched_from = if cached.is_some() && !matches!(spec.git_ref, GitRef::Branch(_)) {
+        mirror.dir().display().to_string()
+    } else if app.offline {
+        if cached.is_none() {
+            bail!(
+                "{} {} is not in the git cache {} (--offline)",
+                spec.name,
+                spec.git_ref,
+                mirror.dir().display()
+            );
+        }
+        mirror.dir().display().to_string()
+    } else {
+        mirror.update(app, spec, urls)?
+    };
+
+    let commit = match mirror.resolve(&spec.git_ref) {
+        Some(commit) => commit,
+        None if app.dry_run => spec.git_ref.name().to_string(),
+        None => bail!(
+            "{} {} does not exist in {}",
+            spec.name,
+            spec.git_ref,
+            fetched_from
+        ),
+    };
+    mirror.checkout(app, spec, &commit, dir)?;
+    Ok(fetched_from)
+}
+
+/// Refuses a tree that is not at the commit the manifest pins, such as a
+/// release tag that was moved.
+pub fn verify_commit(spec: &PackageSpec, commit: Option<&str>) -> Result<()> {
+    let Some(expected) = &spec.commit else {
+        return Ok(());
+    };
+    match commit {
+        Some(commit) if commit.starts_with(expected.as_str()) => Ok(()),
+        Some(commit) => bail!(
+            "{} {} is at commit {}, not the pinned {}; refusing to build it{}",
+            spec.name,
+            spec.git_ref,
+            short_commit(commit),
+            short_commit(expected),
+            match spec.git_ref {
+                GitRef::Tag(_) => " (was the tag moved?)",
+                GitRef::Branch(_) => " (the branch has moved on; update the pinned commit)",
+                GitRef::Commit(_) => "",
+            }
+        ),
+        None => bail!(
+            "cannot verify that {} is at commit {}: {} has no git history",
+            spec.name,
+            short_commit(expected),
+            spec.source.origin()
+        ),
+    }
+}
+
+/// Extracts `archive` into `dir`. Release archives wrap the tree in one
+/// directory (`mmcv-2.1.0/`), which becomes `dir` itself.
+fn extract(app: &App, spec: &PackageSpec, archive: &Path, dir: &Path) -> Result<()> {
+    let mut unpack_dir = dir.as_os_str().to_owned();
+    unpack_dir.push(".unpack");
+    let unpack_dir = PathBuf::from(unpack_dir);
+    if !app.dry_run {
+        if !archive.is_file() {
+            bail!("source tarball {} does not exist", archive.display());
+        }
+        remove_dir_if_exists(&unpack_dir)?;
+        fs::create_dir_all(&unpack_dir)
+            .with_context(|| format!("failed to create {}", unpack_dir.display()))?;
+    }
+
+    let mut tar = Command::new("tar");
+    tar.arg("-xf").arg(archive).arg("-C").arg(&unpack_dir);
+    run_command(
+        app,
+        &format!("extract {}", spec.name),
+        tar,
+        OutputMode::Quiet,
+    )?;
+    if app.dry_run {
+        return Ok(());
+    }
+
+    let entries = fs::read_dir(&unpack_dir)
+        .with_context(|| format!("failed to list {}", unpack_dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("failed to list {}", unpack_dir.display()))?;
+    match entries.as_slice() {
+        [entry] if entry.path().is_dir() => {
+            fs::rename(entry.path(), dir)
+                .with_context(|| format!("failed to move {}", entry.path().display()))?;
+            remove_dir_if_exists(&unpack_dir)
+        }
+        _ => fs::rename(&unpack_dir, dir)
+            .with_context(|| format!("failed to move {}", unpack_dir.display())),
+    }
+}
+
+/// Copies the package's cached tree and its stamp into `dir`, for a bundle.
+pub fn export(spec: &PackageSpec, dir: &Path) -> Result<()> {
+    let cached = cached_dir(spec)?;
+    let target = dir.join(cached.file_name().unwrap_or_default());
+    copy_tree(&cached, &target)?;
+    fs::copy(stamp_path(&cached), stamp_path(&target))
+        .with_context(|| format!("failed to copy {}", stamp_path(&cached).display()))?;
+    Ok(())
+}
+
+/// Installs a tree exported to `dir` into the source cache, replacing the
+/// cached one. Returns false when `dir` has no complete tree for the package.
+pub fn import(spec: &PackageSpec, dir: &Path) -> Result<bool> {
+    let cached = cached_dir(spec)?;
+    let exported = dir.join(cached.file_name().unwrap_or_default());
+    if !exported.is_dir() || !stamp_path(&exported).is_file() {
+        return Ok(false);
+    }
+
+    let stamp = stamp_path(&cached);
+    if stamp.exists() {
+        fs::remove_file(&stamp).with_context(|| format!("failed to remove {}", stamp.display()))?;
+    }
+    remove_dir_if_exists(&cached)?;
+    copy_tree(&exported, &cached)?;
+    // The stamp goes last, so an interrupted import leaves no complete tree.
+    fs::copy(stamp_path(&exported), &stamp)
+        .with_context(|| format!("failed writing {}", stamp.display()))?;
+    Ok(true)
+}
+
+pub fn head_commit(repo: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["rev-parse", "HEAD"])
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// sha256 of a tree's paths, file contents and symlink targets, without
+/// its `.git`: what is copied for a path source.
+fn tree_sha256(root: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .with_context(|| format!("failed to list {}", dir.display()))?
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(|| format!("failed to list {}", dir.display()))?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries.into_iter().rev() {
+            let path = entry.path();
+            if dir == root && entry.file_name() == ".git" {
+                continue;
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let file_type = entry
+                .file_type()
+                .with_context(|| format!("failed to stat {}", path.display()))?;
+            if file_type.is_dir() {
+                hasher.update(format!("dir {}\n", relative.display()));
+                pending.push(path);
+            } else if file_type.is_symlink() {
+                let link = fs::read_link(&path)
+                    .with_context(|| format!("failed to read link {}", path.display()))?;
+                hasher.update(format!("link {} {}\n", relative.display(), link.display()));
+            } else {
+                hasher.update(format!(
+                    "file {} {}\n",
+                    relative.display(),
+                    cache::file_sha256(&path)?
+                ));
+            }
+        }
+    }
+    Ok(cache::hex(&hasher.finalize()))
+}
+
+/// Copies a tree, keeping symlinks as symlinks.
+pub fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    copy_tree_except(from, to, &[])
+}
+
+/// Copies a tree, leaving out the top-level entries named in `skip`.
+fn copy_tree_except(from: &Path, to: &Path, skip: &[&str]) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("failed to create {}", to.display()))?;
+    let entries =
+        fs::read_dir(from).with_context(|| format!("failed to list {}", from.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to list {}", from.display()))?;
+        if skip.iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let file_type = entry
+            .file_type()
+            .with_context(|| format!("failed to stat {}", source.display()))?;
+        if file_type.is_dir() {
+            copy_tree(&source, &target)?;
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(&source)
+                .with_context(|| format!("failed to read link {}", source.display()))?;
+            symlink(&link, &target)
+                .with_context(|| format!("failed to create link {}", target.display()))?;
+        } else {
+            fs::copy(&source, &target).with_context(|| {
+                format!(
+                    "failed to copy {} to {}",
+                    source.display(),
+                    target.display()
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixes::Catalog;
+    use crate::manifest::Manifest;
+
+    const FULL: &str = "0123456789abcdef0123456789abcdef01234567";
+    const OTHER: &str = "fedcba9876543210fedcba9876543210fedcba98";
+
+    /// The bundled mmcv at `git_ref`, pinned to `commit`.
+    fn mmcv(tag: Option<&str>, branch: Option<&str>, commit: Option<&str>) -> PackageSpec {
+        let mut package = Manifest::bundled().unwrap().packages.remove(0);
+        package.tag = tag.map(str::to_string);
+        package.branch = branch.map(str::to_string);
+        package.commit = commit.map(str::to_string);
+        PackageSpec::from_manifest(&package, &Catalog::default(), Path::new("no-patches")).unwrap()
+    }
+
+    #[test]
+    fn accepts_the_pinned_commit_or_its_prefix() {
+        assert!(verify_commit(&mmcv(None, None, None), None).is_ok());
+        assert!(verify_commit(&mmcv(None, None, Some(FULL)), Some(FULL)).is_ok());
+        let spec = mmcv(Some("v2.1.0"), None, Some("0123456789AB"));
+        assert!(verify_commit(&spec, Some(FULL)).is_ok());
+    }
+
+    #[test]
+    fn refuses_a_tree_at_another_commit() {
+        let error = verify_commit(&mmcv(Some("v2.1.0"), None, Some(FULL)), Some(OTHER))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("not the pinned 0123456789ab"), "{error}");
+        assert!(error.contains("was the tag moved?"), "{error}");
+
+        let error = verify_commit(&mmcv(None, Some("main"), Some(FULL)), Some(OTHER))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("the branch has moved on"), "{error}");
+    }
+
+    #[test]
+    fn refuses_a_pinned_tree_without_history() {
+        let error = verify_commit(&mmcv(None, None, Some(FULL)), None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("has no git history"), "{error}");
+    }
+
+    /// A scratch local source tree, removed when dropped.
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "mmaction-install-sources-{name}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("mmcv")).unwrap();
+            fs::write(dir.join("mmcv/version.py"), "__version__ = '2.1.0'\n").unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn keys_local_trees_by_their_content() {
+        let tree = Tree::new("key");
+        let source = Source::Path(tree.0.clone());
+        let key = source.key().unwrap();
+        assert!(key.starts_with(&format!("path {} at no commit, tree ", tree.0.display())));
+        assert_eq!(source.key().unwrap(), key);
+
+        // The top-level .git does not count; everything else does.
+        fs::create_dir(tree.0.join(".git")).unwrap();
+        fs::write(tree.0.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(source.key().unwrap(), key);
+
+        fs::write(tree.0.join("mmcv/version.py"), "__version__ = '2.1.1'\n").unwrap();
+        let edited = source.key().unwrap();
+        assert_ne!(edited, key);
+
+        fs::rename(
+            tree.0.join("mmcv/version.py"),
+            tree.0.join("mmcv/_version.py"),
+        )
+        .unwrap();
+        assert_ne!(source.key().unwrap(), edited);
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+use crate::config::PYPROJECT_FILE;
+use crate::{App, OutputMode, run_command};
+
+pub const UV_LOCK_FILE: &str = "uv.lock";
+const PYPI_SIMPLE: &str = "https://pypi.org/simple";
+
+/// The torch the project pins, as found in uv.lock or pyproject.toml.
+#[derive(Debug, Clone)]
+pub struct TorchRequirement {
+    pub requirement: String,
+    pub index_url: Option<String>,
+    pub origin: &'static str,
+}
+
+/// Resolves the project's torch pin, preferring the exact version locked in
+/// uv.lock over the specifier in pyproject.toml.
+pub fn resolve() -> Result<Option<TorchRequirement>> {
+    if let Some(locked) = from_uv_lock(Path::new(UV_LOCK_FILE))? {
+        return Ok(Some(locked));
+    }
+    from_pyproject(Path::new(PYPROJECT_FILE))
+}
+
+fn read_toml(path: &Path) -> Result<Option<toml::Table>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed reading {}", path.display()))?;
+    let table = toml::from_str(&content).with_context(|| format!("invalid {}", path.display()))?;
+    Ok(Some(table))
+}
+
+fn from_uv_lock(path: &Path) -> Result<Option<TorchRequirement>> {
+    let Some(lock) = read_toml(path)? else {
+        return Ok(None);
+    };
+
+    let entries: Vec<&toml::Table> = lock
+        .get("package")
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_table)
+        .filter(|package| package.get("name").and_then(toml::Value::as_str) == Some("torch"))
+        .collect();
+
+    // Several entries mean platform-specific resolutions; the pyproject
+    // specifier (plus its index) is the safer choice then.
+    let [entry] = entries.as_slice() else {
+        return Ok(None);
+    };
+    let Some(version) = entry.get("version").and_then(toml::Value::as_str) else {
+        return Ok(None);
+    };
+
+    let index_url = entry
+        .get("source")
+        .and_then(|source| source.get("registry"))
+        .and_then(toml::Value::as_str)
+        .filter(|url| url.trim_end_matches('/') != PYPI_SIMPLE)
+        .map(str::to_string);
+
+    Ok(Some(TorchRequirement {
+        requirement: format!("torch=={version}"),
+        index_url,
+        origin: UV_LOCK_FILE,
+    }))
+}
+
+fn from_pyproject(path: &Path) -> Result<Option<TorchRequirement>> {
+    let Some(pyproject) = read_toml(path)? else {
+        return Ok(None);
+    };
+
+    let project_deps = pyproject
+        .get("project")
+        .and_then(|project| project.get("dependencies"))
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten();
+    let group_deps = pyproject
+        .get("dependency-groups")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flat_map(|groups| groups.values())
+        .filter_map(toml::Value::as_array)
+        .flatten();
+
+    let Some(requirement) = project_deps
+        .chain(group_deps)
+        .filter_map(toml::Value::as_str)
+        .find(|requirement| requirement_name(requirement) == "torch")
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(TorchRequirement {
+        requirement: requirement.trim().to_string(),
+        index_url: uv_source_index(&pyproject, "torch"),
+        origin: PYPROJECT_FILE,
+    }))
+}
+
+fn requirement_name(requirement: &str) -> String {
+    requirement
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Follows `[tool.uv.sources] <name> = { index = "..." }` to the matching
+/// `[[tool.uv.index]]` URL.
+fn uv_source_index(pyproject: &toml::Table, name: &str) -> Option<String> {
+    let uv = pyproject.get("tool")?.get("uv")?;
+    let index_name = uv.get("sources")?.get(name)?.get("index")?.as_str()?;
+    uv.get("index")?
+        .as_array()?
+        .iter()
+        .find(|index| index.get("name").and_then(toml::Value::as_str) == Some(index_name))?
+        .get("url")?
+        .as_str()
+        .map(str::to_string)
+}
+
+pub fn ensure(app: &App, warnings: &mut Vec<String>) -> Result<()> {
+    let Some(torch) = resolve()? else {
+        warnings.push(format!(
+            "no torch requirement found in {UV_LOCK_FILE} or {PYPROJECT_FILE}; wheels are built against the torch already in the venv"
+        ));
+        return Ok(());
+    };
+    app.plan(&format!(
+        "project pins {} ({})",
+        torch.requirement, torch.origin
+    ));
+
+    let mut install = Command::new("uv");
+    install
+        .arg("pip")
+        .arg("install")
+        .arg("--python")
+        .arg(app.python_bin());
+    if let Some(index_url) = &torch.index_url {
+        install.arg("--extra-index-url").arg(index_url);
+    }
+    install.arg(&torch.requirement);
+    run_command(
+        app,
+        &format!("install {}", torch.requirement),
+        install,
+        OutputMode::Quiet,
+    )
+}
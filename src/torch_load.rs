@@ -0,0 +1,306 @@
+const CALL: &[u8] = b"torch.load";
+const KEYWORD: &str = "weights_only";
+
+#[derive(Debug, Default)]
+pub struct Rewrite {
+    pub content: String,
+    pub patched: usize,
+    pub already_set: usize,
+    pub unbalanced: usize,
+}
+
+/// One top-level argument, as byte offsets of its first and past-the-last
+/// code byte (whitespace and comments excluded).
+struct Arg {
+    start: usize,
+    end: usize,
+}
+
+#[derive(Default)]
+struct Call {
+    args: Vec<Arg>,
+    /// Where the arguments end with a comma, the offset just past it.
+    trailing_comma: Option<usize>,
+}
+
+/// Adds `weights_only=False` to the `torch.load(...)` calls in Python source.
+/// The scanner knows just enough Python for that: strings (any prefix, single
+/// or triple quoted), comments and bracket nesting, so it finds the call's
+/// real closing parenthesis across lines and splits its top-level arguments.
+pub fn rewrite(source: &str) -> Rewrite {
+    let bytes = source.as_bytes();
+    let mut rewrite = Rewrite::default();
+    // Only insertions, so nested calls never produce overlapping edits.
+    let mut inserts: Vec<(usize, &str)> = Vec::new();
+
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'#' => index = skip_comment(bytes, index),
+            b'\'' | b'"' => index = skip_string(bytes, index),
+            _ => {
+                let Some(open) = call_at(bytes, index) else {
+                    index += 1;
+                    continue;
+                };
+                match arguments(bytes, open) {
+                    None => rewrite.unbalanced += 1,
+                    Some(call) => {
+                        if let Some(edit) = plan_call(bytes, open, &call) {
+                            inserts.extend(edit);
+                            rewrite.patched += 1;
+                        } else {
+                            rewrite.already_set += 1;
+                        }
+                    }
+                }
+                // Keep scanning inside the arguments for nested calls.
+                index = open + 1;
+            }
+        }
+    }
+
+    inserts.sort_by_key(|(position, _)| *position);
+    let mut content = String::with_capacity(source.len() + inserts.len() * 24);
+    let mut copied = 0;
+    for (position, text) in inserts {
+        content.push_str(&source[copied..position]);
+        content.push_str(text);
+        copied = position;
+    }
+    content.push_str(&source[copied..]);
+    rewrite.content = content;
+    rewrite
+}
+
+/// The edits for one call, or `None` when it already sets `weights_only`.
+fn plan_call(bytes: &[u8], open: usize, call: &Call) -> Option<Vec<(usize, &'static str)>> {
+    let text = |arg: &Arg| &bytes[arg.start..arg.end];
+    let args = &call.args;
+    if args.iter().any(|arg| is_keyword(text(arg))) {
+        return None;
+    }
+
+    // `**kwargs` may carry weights_only; make False the default it overrides.
+    if let Some(kwargs) = args.iter().find(|arg| text(arg).starts_with(b"**")) {
+        return Some(vec![
+            (kwargs.start + 2, "{'weights_only': False, **"),
+            (kwargs.end, "}"),
+        ]);
+    }
+
+    Some(match (args.last(), call.trailing_comma) {
+        (None, _) => vec![(open + 1, "weights_only=False")],
+        (Some(_), Some(comma)) => vec![(comma, " weights_only=False,")],
+        (Some(last), None) => vec![(last.end, ", weights_only=False")],
+    })
+}
+
+fn is_keyword(arg: &[u8]) -> bool {
+    let Some(rest) = arg.strip_prefix(KEYWORD.as_bytes()) else {
+        return false;
+    };
+    let rest = rest.trim_ascii_start();
+    rest.starts_with(b"=") && !rest.starts_with(b"==")
+}
+
+/// The offset of the `(` if a `torch.load` call starts at `index`.
+fn call_at(bytes: &[u8], index: usize) -> Option<usize> {
+    if !bytes[index..].starts_with(CALL) {
+        return None;
+    }
+    if index > 0 && (is_identifier(bytes[index - 1]) || bytes[index - 1] == b'.') {
+        return None;
+    }
+    let mut open = index + CALL.len();
+    while bytes
+        .get(open)
+        .is_some_and(|byte| matches!(byte, b' ' | b'\t'))
+    {
+        open += 1;
+    }
+    (bytes.get(open) == Some(&b'(')).then_some(open)
+}
+
+/// Splits the call's top-level arguments, or `None` if the parenthesis never
+/// closes.
+fn arguments(bytes: &[u8], open: usize) -> Option<Call> {
+    let mut call = Call::default();
+    let mut current: Option<Arg> = None;
+    let mut depth = 0usize;
+    let mut index = open + 1;
+
+    while index < bytes.len() {
+        let byte = bytes[index];
+        let end = match byte {
+            b'#' => {
+                index = skip_comment(bytes, index);
+                continue;
+            }
+            _ if byte.is_ascii_whitespace() || byte == b'\\' => {
+                index += 1;
+                continue;
+            }
+            b')' | b']' | b'}' if depth == 0 => {
+                call.args.extend(current);
+                return Some(call);
+            }
+            b',' if depth == 0 => {
+                call.args.extend(current.take());
+                call.trailing_comma = Some(index + 1);
+                index += 1;
+                continue;
+            }
+            b'\'' | b'"' => skip_string(bytes, index),
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                index + 1
+            }
+            b')' | b']' | b'}' => {
+                depth -= 1;
+                index + 1
+            }
+            _ => index + 1,
+        };
+
+        call.trailing_comma = None;
+        match &mut current {
+            Some(arg) => arg.end = end,
+            None => current = Some(Arg { start: index, end }),
+        }
+        index = end;
+    }
+    None
+}
+
+fn skip_comment(bytes: &[u8], index: usize) -> usize {
+    bytes[index..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |offset| index + offset)
+}
+
+/// Skips a string literal starting at its opening quote. A backslash always
+/// protects the next byte, which holds for raw strings too as far as finding
+/// the end goes. Prefixes (`r`, `b`, `f`, ...) were already consumed as code.
+fn skip_string(bytes: &[u8], index: usize) -> usize {
+    let quote = bytes[index];
+    let triple = bytes[index..].starts_with(&[quote; 3]);
+    let mut position = index + if triple { 3 } else { 1 };
+
+    while position < bytes.len() {
+        match bytes[position] {
+            b'\\' => position += 2,
+            byte if byte == quote => {
+                if !triple {
+                    return position + 1;
+                }
+                if bytes[position..].starts_with(&[quote; 3]) {
+                    return position + 3;
+                }
+                position += 1;
+            }
+            // An unterminated single-quoted string ends at the line break.
+            b'\n' if !triple => return position,
+            _ => position += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn is_identifier(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewritten(source: &str) -> String {
+        rewrite(source).content
+    }
+
+    #[test]
+    fn appends_to_simple_and_empty_calls() {
+        assert_eq!(
+            rewritten("x = torch.load(f)\n"),
+            "x = torch.load(f, weights_only=False)\n"
+        );
+        assert_eq!(
+            rewritten("torch.load()\n"),
+            "torch.load(weights_only=False)\n"
+        );
+        assert_eq!(
+            rewritten("torch.load (f)\n"),
+            "torch.load (f, weights_only=False)\n"
+        );
+    }
+
+    #[test]
+    fn finds_the_closing_parenthesis_of_nested_calls() {
+        assert_eq!(
+            rewritten("torch.load(f, map_location=get_loc(dev[0], {'a': (1, 2)}))\n"),
+            "torch.load(f, map_location=get_loc(dev[0], {'a': (1, 2)}), weights_only=False)\n"
+        );
+        // A torch.load inside another torch.load's arguments gets its own edit.
+        let result = rewrite("torch.load(torch.load(a))\n");
+        assert_eq!(result.patched, 2);
+        assert_eq!(
+            result.content,
+            "torch.load(torch.load(a, weights_only=False), weights_only=False)\n"
+        );
+    }
+
+    #[test]
+    fn handles_multi_line_calls() {
+        assert_eq!(
+            rewritten("torch.load(\n    f,\n    map_location='cpu',\n)\n"),
+            "torch.load(\n    f,\n    map_location='cpu', weights_only=False,\n)\n"
+        );
+        assert_eq!(
+            rewritten("torch.load(f,  # the file\n           map_location='cpu')  # done\n"),
+            "torch.load(f,  # the file\n           map_location='cpu', weights_only=False)  # done\n"
+        );
+    }
+
+    #[test]
+    fn defaults_weights_only_under_kwargs() {
+        assert_eq!(
+            rewritten("torch.load(f, **kwargs)\n"),
+            "torch.load(f, **{'weights_only': False, **kwargs})\n"
+        );
+    }
+
+    #[test]
+    fn leaves_calls_that_set_weights_only() {
+        let source = "torch.load(f, weights_only=True)\ntorch.load(f, weights_only = cfg.safe)\n";
+        let result = rewrite(source);
+        assert_eq!(result.content, source);
+        assert_eq!((result.patched, result.already_set), (0, 2));
+        // A comparison is not the keyword.
+        assert_eq!(
+            rewritten("torch.load(weights_only == x)\n"),
+            "torch.load(weights_only == x, weights_only=False)\n"
+        );
+    }
+
+    #[test]
+    fn skips_strings_comments_and_other_names() {
+        let source = "# torch.load(f)\ns = 'torch.load(f)'\ndoc = \"\"\"\ntorch.load(f)\n\"\"\"\nmy_torch.load(f)\nx.torch.load(f)\n";
+        let result = rewrite(source);
+        assert_eq!(result.content, source);
+        assert_eq!(result.patched, 0);
+        // Parentheses inside string arguments do not end the call.
+        assert_eq!(
+            rewritten("torch.load(f'{p})', r\"(\")\n"),
+            "torch.load(f'{p})', r\"(\", weights_only=False)\n"
+        );
+    }
+
+    #[test]
+    fn counts_calls_that_never_close() {
+        let result = rewrite("torch.load(f,\n");
+        assert_eq!((result.patched, result.unbalanced), (0, 1));
+        assert_eq!(result.content, "torch.load(f,\n");
+    }
+}
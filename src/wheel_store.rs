@@ -0,0 +1,298 @@
+use std::fs::{self, File, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::cache::{self, BuildRecord, CacheKey};
+use crate::pipeline::PackageSpec;
+use crate::{App, WHEELHOUSE, lock_exclusive};
+
+const INDEX_DIR: &str = "index";
+const OBJECTS_DIR: &str = "objects";
+/// Group-writable and setgid, so everyone in the group of the store root
+/// can add wheels and lock files to the directories any of them created.
+const SHARED_DIR_MODE: u32 = 0o2775;
+
+/// A wheel store shared by every project that points at it. Wheels are kept
+/// once under `objects/` by their sha256, and `index/<package>/` maps the
+/// fingerprint of each build (package, version and cache key) to the build
+/// record of the wheel it produced.
+pub struct WheelStore {
+    root: PathBuf,
+}
+
+impl WheelStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Blocks other installs from building or publishing the same wheel
+    /// until the file is dropped. None in dry-run.
+    pub fn lock(&self, app: &App, spec: &PackageSpec, key: &CacheKey) -> Result<Option<File>> {
+        if app.dry_run {
+            return Ok(None);
+        }
+        let entry = self.entry_path(spec, key);
+        if let Some(parent) = entry.parent() {
+            self.create_dir(parent)?;
+        }
+        let mut path = entry.into_os_string();
+        path.push(".lock");
+        lock_exclusive(app, Path::new(&path), "the wheel store").map(Some)
+    }
+
+    /// The build record of the stored wheel for this build, if there is one
+    /// and its wheel is still in the store.
+    pub fn find(&self, spec: &PackageSpec, key: &CacheKey) -> Result<Option<BuildRecord>> {
+        let path = self.entry_path(spec, key);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+        // An entry that no longer parses is replaced by the next publish.
+        let Ok(record) = serde_json::from_str::<BuildRecord>(&content) else {
+            return Ok(None);
+        };
+        Ok(
+            (record.cache_key == *key && self.object_path(&record.sha256).is_file())
+                .then_some(record),
+        )
+    }
+
+    /// Copies the stored wheel and its build record into the wheelhouse.
+    /// False, leaving the wheelhouse alone, when the copy does not match the
+    /// recorded sha256.
+    pub fn pull(&self, record: &BuildRecord) -> Result<bool> {
+        let object = self.object_path(&record.sha256);
+        fs::create_dir_all(WHEELHOUSE).context("failed to create .wheelhouse directory")?;
+        let wheel = Path::new(WHEELHOUSE).join(&record.wheel);
+        fs::copy(&object, &wheel)
+            .with_context(|| format!("failed to copy {}", object.display()))?;
+        if cache::file_sha256(&wheel)? != record.sha256 {
+            cache::remove_wheel(&wheel)?;
+            return Ok(false);
+        }
+        record.write()?;
+        Ok(true)
+    }
+
+    /// Adds a wheel built in the wheelhouse and its record to the store.
+    /// Both land under a temporary name first, so other installs never see a
+    /// partial file.
+    pub fn publish(&self, spec: &PackageSpec, record: &BuildRecord, wheel: &Path) -> Result<()> {
+        let object = self.object_path(&record.sha256);
+        if !object.is_file() || cache::file_sha256(&object)? != record.sha256 {
+            self.write_atomically(&object, |temporary| {
+                fs::copy(wheel, temporary)
+                    .map(drop)
+                    .with_context(|| format!("failed to copy {}", wheel.display()))
+            })?;
+        }
+        let json =
+            serde_json::to_string_pretty(record).context("failed to serialize build record")?;
+        self.write_atomically(&self.entry_path(spec, &record.cache_key), |temporary| {
+            fs::write(temporary, json + "\n")
+                .with_context(|| format!("failed writing {}", temporary.display()))
+        })
+    }
+
+    fn entry_path(&self, spec: &PackageSpec, key: &CacheKey) -> PathBuf {
+        self.root.join(INDEX_DIR).join(&spec.name).join(format!(
+            "{}.json",
+            key.fingerprint(&spec.name, &spec.version)
+        ))
+    }
+
+    /// `objects/ab/abcdef….whl`: the first two hex digits keep directories
+    /// small.
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.root
+            .join(OBJECTS_DIR)
+            .join(&sha256[..2.min(sha256.len())])
+            .join(format!("{sha256}.whl"))
+    }
+
+    /// Creates `dir` and its missing parents with `SHARED_DIR_MODE`.
+    fn create_dir(&self, dir: &Path) -> Result<()> {
+        let missing: Vec<&Path> = dir
+            .ancestors()
+            .take_while(|path| !path.exists() && path.starts_with(&self.root))
+            .collect();
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        for path in missing {
+            fs::set_permissions(path, Permissions::from_mode(SHARED_DIR_MODE))
+                .with_context(|| format!("failed to make {} group-writable", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn write_atomically(&self, path: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            self.create_dir(parent)?;
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(format!(".tmp-{}", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+        if let Err(error) = write(&temporary) {
+            let _ = fs::remove_file(&temporary);
+            return Err(error);
+        }
+        fs::rename(&temporary, path)
+            .with_context(|| format!("failed to move {} into place", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixes::Catalog;
+    use crate::manifest::Manifest;
+
+    /// A scratch store, with a built wheel next to it, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "mmaction-install-store-{name}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn store(&self) -> WheelStore {
+            WheelStore::new(self.0.join("store"))
+        }
+
+        /// A built wheel and its record.
+        fn wheel(&self, content: &str) -> (PathBuf, BuildRecord) {
+            let wheel = self.0.join("mmengine-0.10.7-py3-none-any.whl");
+            fs::write(&wheel, content).unwrap();
+            let record = serde_json::from_value(serde_json::json!({
+                "package": "mmengine",
+                "version": "0.10.7",
+                "wheel": "mmengine-0.10.7-py3-none-any.whl",
+                "sha256": cache::file_sha256(&wheel).unwrap(),
+                "cache_key": key(),
+                "source": { "repository": "https://github.com/open-mmlab/mmengine", "ref": "v0.10.7" },
+                "patches": [],
+                "compiler": null,
+                "build_env": {},
+                "built_at": "2026-10-16T09:30:00Z",
+                "build_seconds": 1.0,
+            }))
+            .unwrap();
+            (wheel, record)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn spec() -> PackageSpec {
+        let manifest = Manifest::bundled().unwrap();
+        let package = manifest
+            .packages
+            .iter()
+            .find(|package| package.name == "mmengine")
+            .unwrap();
+        PackageSpec::from_manifest(package, &Catalog::default(), Path::new("no-patches")).unwrap()
+    }
+
+    fn key() -> CacheKey {
+        CacheKey {
+            python_tag: "cp311".to_string(),
+            torch: "2.4.1+cu121".to_string(),
+            variant: "auto-cu121".to_string(),
+            patches: "1a2b3c".to_string(),
+            source: "https://github.com/open-mmlab/mmengine".to_string(),
+        }
+    }
+
+    #[test]
+    fn finds_a_published_wheel_by_its_build() {
+        let scratch = Scratch::new("find");
+        let store = scratch.store();
+        let (wheel, record) = scratch.wheel("wheel");
+        assert!(store.find(&spec(), &key()).unwrap().is_none());
+
+        store.publish(&spec(), &record, &wheel).unwrap();
+        let found = store.find(&spec(), &key()).unwrap().unwrap();
+        assert_eq!(found.sha256, record.sha256);
+        assert_eq!(
+            fs::read_to_string(store.object_path(&record.sha256)).unwrap(),
+            "wheel"
+        );
+
+        let other = CacheKey {
+            torch: "2.5.1+cu121".to_string(),
+            ..key()
+        };
+        assert!(store.find(&spec(), &other).unwrap().is_none());
+    }
+
+    #[test]
+    fn forgets_entries_whose_wheel_is_gone_or_unreadable() {
+        let scratch = Scratch::new("gone");
+        let store = scratch.store();
+        let (wheel, record) = scratch.wheel("wheel");
+        store.publish(&spec(), &record, &wheel).unwrap();
+
+        fs::remove_file(store.object_path(&record.sha256)).unwrap();
+        assert!(store.find(&spec(), &key()).unwrap().is_none());
+
+        store.publish(&spec(), &record, &wheel).unwrap();
+        fs::write(store.entry_path(&spec(), &key()), "{").unwrap();
+        assert!(store.find(&spec(), &key()).unwrap().is_none());
+    }
+
+    #[test]
+    fn replaces_a_corrupt_object_on_publish() {
+        let scratch = Scratch::new("corrupt");
+        let store = scratch.store();
+        let (wheel, record) = scratch.wheel("wheel");
+        store.publish(&spec(), &record, &wheel).unwrap();
+
+        let object = store.object_path(&record.sha256);
+        fs::write(&object, "truncated").unwrap();
+        store.publish(&spec(), &record, &wheel).unwrap();
+        assert_eq!(cache::file_sha256(&object).unwrap(), record.sha256);
+        let names: Vec<String> = fs::read_dir(object.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, [format!("{}.whl", record.sha256)]);
+    }
+
+    #[test]
+    fn creates_group_writable_directories() {
+        let scratch = Scratch::new("mode");
+        let store = scratch.store();
+        let (wheel, record) = scratch.wheel("wheel");
+        store.publish(&spec(), &record, &wheel).unwrap();
+
+        for dir in [
+            store.root().to_path_buf(),
+            store.root().join(INDEX_DIR).join("mmengine"),
+            store
+                .object_path(&record.sha256)
+                .parent()
+                .unwrap()
+                .to_path_buf(),
+        ] {
+            let mode = fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o7777, SHARED_DIR_MODE, "{}", dir.display());
+        }
+        let mode = fs::metadata(&scratch.0).unwrap().permissions().mode();
+        assert_ne!(mode & 0o7777, SHARED_DIR_MODE);
+    }
+}